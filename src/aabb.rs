@@ -0,0 +1,106 @@
+use crate::vec3::*;
+use crate::ray::*;
+
+// Axis Aligned Bounding Box, stored as its two extreme corners
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub minimum: Point3,
+    pub maximum: Point3,
+}
+
+impl Aabb {
+
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        // Accept the corners in any order
+        Aabb {
+            minimum: Point3 { x: a.x.min(b.x), y: a.y.min(b.y), z: a.z.min(b.z) },
+            maximum: Point3 { x: a.x.max(b.x), y: a.y.max(b.y), z: a.z.max(b.z) },
+        }
+    }
+
+    // Slab test, using the Andrew Kensler formulation from the book
+    pub fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> bool {
+        self.clip(ray, tmin, tmax).is_some()
+    }
+
+    // The part of [tmin, tmax] where the ray is inside the box, if any
+    pub fn clip(&self, ray: &Ray, mut tmin: f64, mut tmax: f64) -> Option<(f64, f64)> {
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.dir[axis];
+            let mut t0 = (self.minimum[axis] - ray.orig[axis]) * inv_d;
+            let mut t1 = (self.maximum[axis] - ray.orig[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            tmin = if t0 > tmin { t0 } else { tmin };
+            tmax = if t1 < tmax { t1 } else { tmax };
+            if tmax <= tmin {
+                return None;
+            }
+        }
+        Some((tmin, tmax))
+    }
+
+    pub fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
+        Aabb {
+            minimum: Point3 {
+                x: box0.minimum.x.min(box1.minimum.x),
+                y: box0.minimum.y.min(box1.minimum.y),
+                z: box0.minimum.z.min(box1.minimum.z),
+            },
+            maximum: Point3 {
+                x: box0.maximum.x.max(box1.maximum.x),
+                y: box0.maximum.y.max(box1.maximum.y),
+                z: box0.maximum.z.max(box1.maximum.z),
+            },
+        }
+    }
+
+    // Flat shapes give a box with no thickness along one axis, which the slab test never hits.
+    // Widen any such axis a little.
+    pub fn padded(self) -> Aabb {
+        let delta = 1e-4;
+        let mut minimum = self.minimum;
+        let mut maximum = self.maximum;
+        if maximum.x - minimum.x < delta { minimum.x -= delta / 2.0; maximum.x += delta / 2.0; }
+        if maximum.y - minimum.y < delta { minimum.y -= delta / 2.0; maximum.y += delta / 2.0; }
+        if maximum.z - minimum.z < delta { minimum.z -= delta / 2.0; maximum.z += delta / 2.0; }
+        Aabb { minimum, maximum }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_aabb_hit() {
+    let bbox = Aabb::new(Point3 { x: -1.0, y: -1.0, z: -1.0 }, Point3 { x: 1.0, y: 1.0, z: 1.0 });
+    let towards = Ray::new(Point3 { x: 0.0, y: 0.0, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let away = Ray::new(Point3 { x: 0.0, y: 0.0, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let beside = Ray::new(Point3 { x: 2.0, y: 0.0, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+
+    assert!(bbox.hit(&towards, 0.001, f64::MAX));
+    assert!(!bbox.hit(&away, 0.001, f64::MAX));
+    assert!(!bbox.hit(&beside, 0.001, f64::MAX));
+    // the box is 4 units away, so a short ray should miss it
+    assert!(!bbox.hit(&towards, 0.001, 3.0));
+}
+
+#[test]
+fn test_aabb_surrounding_box() {
+    let a = Aabb::new(Point3 { x: 0.0, y: 0.0, z: 0.0 }, Point3 { x: 1.0, y: 1.0, z: 1.0 });
+    let b = Aabb::new(Point3 { x: -2.0, y: 0.5, z: 0.5 }, Point3 { x: -1.0, y: 3.0, z: 0.7 });
+    let c = Aabb::surrounding_box(a, b);
+    assert_eq!(c.minimum, Point3 { x: -2.0, y: 0.0, z: 0.0 });
+    assert_eq!(c.maximum, Point3 { x: 1.0, y: 3.0, z: 1.0 });
+    assert_eq!(a.surface_area(), 6.0);
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::ray::*;
+use crate::environment::EnvironmentMap;
+
+
+// What a ray sees when it escapes the scene without hitting anything
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Background {
+    // A single colour in every direction, black for scenes lit only by their lights
+    Solid(Colour),
+    // The white to blue sky from the first book
+    #[default]
+    Gradient,
+    // An HDR image all around, which lights the scene like any other light
+    Environment(Arc<EnvironmentMap>),
+}
+
+impl Background {
+
+    pub fn colour(&self, ray: &Ray) -> Colour {
+        match self {
+            Background::Solid(colour) => *colour,
+            Background::Gradient => {
+                let unit_direction = ray.dir.unit_vector();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                // linear fade between white and blue (blue at t, white at 0->t)
+                (1.0 - t) * Colour {x: 1.0,y: 1.0,z: 1.0,} + t * Colour{x: 0.5,y: 0.7,z: 1.0}
+            }
+            Background::Environment(map) => map.radiance(ray.dir),
+        }
+    }
+
+    // A direction to look for light in and its density per unit of solid angle, for the
+    // backgrounds bright enough in places to be worth sampling like a light
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        match self {
+            Background::Environment(map) => Some(map.sample()),
+            _ => None,
+        }
+    }
+
+    // How likely sample is to pick direction, 0 for backgrounds that are never sampled
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match self {
+            Background::Environment(map) => map.pdf(direction),
+            _ => 0.0,
+        }
+    }
+
+    // "black", "gradient", "r,g,b" or the path of an .hdr or .pfm environment image
+    pub fn parse(s: &str) -> Result<Background, String> {
+        let path = Path::new(s.trim());
+        if matches!(path.extension().and_then(|ext| ext.to_str()), Some("hdr") | Some("pfm")) {
+            return Ok(Background::Environment(Arc::new(EnvironmentMap::load(path, 0.0, 1.0)?)));
+        }
+        match s.trim() {
+            "black" => Ok(Background::Solid(Colour::new())),
+            "gradient" => Ok(Background::Gradient),
+            rgb => {
+                let parts: Vec<&str> = rgb.split(',').collect();
+                let values: Result<Vec<f64>, _> = parts.iter().map(|p| p.trim().parse::<f64>()).collect();
+                match values {
+                    Ok(v) if v.len() == 3 => Ok(Background::Solid(Colour { x: v[0], y: v[1], z: v[2] })),
+                    _ => Err(format!("`{}` is not a background, expected black, gradient, r,g,b or an .hdr or .pfm image", s)),
+                }
+            }
+        }
+    }
+}
+
+
+// TEST
+#[test]
+fn test_parse_background() {
+    assert_eq!(Background::parse("black"), Ok(Background::Solid(Colour::new())));
+    assert_eq!(Background::parse("gradient"), Ok(Background::Gradient));
+    assert_eq!(Background::parse("0.1, 0.2,0.3"), Ok(Background::Solid(Colour { x: 0.1, y: 0.2, z: 0.3 })));
+    assert!(Background::parse("0.1,0.2").is_err());
+    assert!(Background::parse("purple").is_err());
+}
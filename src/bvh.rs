@@ -0,0 +1,175 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::aabb::*;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+
+// Bounding Volume Hierarchy
+// Each node only holds two children, a child is either another node or a leaf object.
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: Aabb,
+}
+
+// Everything the builder needs about an object, computed once up front
+struct BuildItem {
+    object: Arc<dyn Hittable>,
+    bbox: Aabb,
+    centroid: Point3,
+}
+
+impl BvhNode {
+
+    // Panics if there are no objects, or if any of them cannot be bounded.
+    // Use HittableList::into_bvh when the list may hold unbounded objects.
+    pub fn new(objects: Vec<Arc<dyn Hittable>>) -> BvhNode {
+        assert!(!objects.is_empty(), "Cannot build a BVH without any objects");
+
+        let mut items: Vec<BuildItem> = objects
+            .into_iter()
+            .map(|object| {
+                let bbox = object.bounding_box().expect("No bounding box in BvhNode constructor");
+                BuildItem { object, bbox, centroid: bbox.centroid() }
+            })
+            .collect();
+
+        if items.len() == 1 {
+            // a single object still needs two children
+            let only = items.remove(0);
+            return BvhNode { left: only.object.clone(), right: only.object, bbox: only.bbox };
+        }
+        BvhNode::build(&mut items)
+    }
+
+    fn build(items: &mut [BuildItem]) -> BvhNode {
+        let split = sah_split(items);
+        let (left_items, right_items) = items.split_at_mut(split);
+
+        let left = BvhNode::build_child(left_items);
+        let right = BvhNode::build_child(right_items);
+        let bbox = Aabb::surrounding_box(
+            left.bounding_box().unwrap(),
+            right.bounding_box().unwrap(),
+        );
+        BvhNode { left, right, bbox }
+    }
+
+    fn build_child(items: &mut [BuildItem]) -> Arc<dyn Hittable> {
+        if items.len() == 1 {
+            items[0].object.clone()
+        } else {
+            Arc::new(BvhNode::build(items))
+        }
+    }
+}
+
+fn sort_by_axis(items: &mut [BuildItem], axis: usize) {
+    items.sort_by(|a, b| {
+        a.centroid[axis]
+            .partial_cmp(&b.centroid[axis])
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+// Surface Area Heuristic:
+// for every axis, sweep the objects sorted by centroid and pick the split that minimises
+// SA(left) * N(left) + SA(right) * N(right).
+// Leaves the items sorted along the chosen axis and returns the index of the first right item.
+fn sah_split(items: &mut [BuildItem]) -> usize {
+    let n = items.len();
+    if n == 2 {
+        return 1;
+    }
+
+    let mut best_cost = f64::MAX;
+    let mut best_axis = 0;
+    let mut best_split = n / 2;
+    let mut right_areas = vec![0.0; n];
+
+    for axis in 0..3 {
+        sort_by_axis(items, axis);
+
+        // right_areas[i] is the surface area of the box around items[i..n]
+        let mut right_box = items[n - 1].bbox;
+        right_areas[n - 1] = right_box.surface_area();
+        for i in (1..n - 1).rev() {
+            right_box = Aabb::surrounding_box(right_box, items[i].bbox);
+            right_areas[i] = right_box.surface_area();
+        }
+
+        let mut left_box = items[0].bbox;
+        for i in 1..n {
+            left_box = Aabb::surrounding_box(left_box, items[i - 1].bbox);
+            let cost = i as f64 * left_box.surface_area() + (n - i) as f64 * right_areas[i];
+            if cost < best_cost {
+                best_cost = cost;
+                best_axis = axis;
+                best_split = i;
+            }
+        }
+    }
+
+    if best_axis != 2 {
+        sort_by_axis(items, best_axis);
+    }
+    best_split
+}
+
+impl Hittable for BvhNode {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        if !self.bbox.hit(ray, tmin, tmax) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(ray, tmin, tmax);
+        let closest_so_far = match &hit_left {
+            Some(hit) => hit.t,
+            None => tmax,
+        };
+        let hit_right = self.right.hit(ray, tmin, closest_so_far);
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_bvh_matches_linear_list() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour { x: 0.5, y: 0.5, z: 0.5 }));
+    let mut linear = HittableList::new();
+    let mut objects: Vec<Arc<dyn Hittable>> = Vec::new();
+    for i in 0..50 {
+        let center = Vec3::random_range(-10.0, 10.0);
+        let sphere: Arc<dyn Hittable> = Arc::new(crate::sphere::Sphere { center, radius: 0.2 + (i % 5) as f64 * 0.3, mat_ptr: mat.clone() });
+        linear.add(sphere.clone());
+        objects.push(sphere);
+    }
+    let bvh = BvhNode::new(objects);
+
+    for _ in 0..500 {
+        let ray = Ray::new(Vec3::random_range(-15.0, 15.0), Vec3::random_range(-1.0, 1.0));
+        let expected = linear.hit(&ray, 0.001, f64::MAX).map(|h| h.t);
+        let got = bvh.hit(&ray, 0.001, f64::MAX).map(|h| h.t);
+        assert_eq!(expected, got);
+    }
+}
+
+#[test]
+fn test_bvh_single_object() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour { x: 0.5, y: 0.5, z: 0.5 }));
+    let sphere: Arc<dyn Hittable> = Arc::new(crate::sphere::Sphere { center: Point3 { x: 0.0, y: 0.0, z: -2.0 }, radius: 1.0, mat_ptr: mat });
+    let bvh = BvhNode::new(vec![sphere]);
+    let ray = Ray::new(Point3::new(), Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let hit = bvh.hit(&ray, 0.001, f64::MAX).unwrap();
+    assert!((hit.t - 1.0).abs() < 1e-9);
+}
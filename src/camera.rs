@@ -1,7 +1,7 @@
 
 use crate::vec3::*;
 use crate::ray::*;
-use std::f64::consts::PI;
+use crate::random::*;
 
 #[derive(Copy, Clone)]
 pub struct Camera {
@@ -13,7 +13,9 @@ pub struct Camera {
     pub v: Vec3,
     pub w: Vec3,
     pub lens_radius: f64,
-
+    // shutter open and close times, rays are spread evenly between them
+    pub time0: f64,
+    pub time1: f64,
 }
 
 
@@ -49,17 +51,26 @@ impl Camera {
             - focus_dist * w;
 
         Camera {
-            origin: origin, 
-            lower_left_corner: lower_left_corner, 
-            horizontal: horizontal, 
-            vertical: vertical,
-            u: u,
-            v: v,
-            w: w,
+            origin, 
+            lower_left_corner, 
+            horizontal, 
+            vertical,
+            u,
+            v,
+            w,
             lens_radius: aperture/2.0,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
 
+    // Keep the shutter open from open to close, so anything moving in that time is blurred
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.time0 = open;
+        self.time1 = close;
+        self
+    }
+
 
     pub fn getray(self, s:f64, t:f64) -> Ray {
         let raydir:Vec3 = self.lens_radius * Vec3::random_in_unit_disk();
@@ -70,11 +81,14 @@ impl Camera {
         //                 + t * self.vertical - self.origin;
         
 
-        Ray{
-            orig: self.origin + offset,
-            dir:  self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset 
-            
-        }
+        // a closed shutter is an instant, no need to draw a random number for it
+        let time = if self.time1 > self.time0 { random_range(self.time0, self.time1) } else { self.time0 };
+
+        Ray::with_time(
+            self.origin + offset,
+            self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset,
+            time,
+        )
     }
 
 }
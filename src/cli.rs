@@ -0,0 +1,197 @@
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use std::path::PathBuf;
+
+use crate::image_io::ImageFormat;
+use crate::background::Background;
+use crate::integrator::Heuristic;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SceneChoice {
+    // The final scene from the book, lots of small random spheres
+    Random,
+    // Ground, a diffuse, a hollow glass and a metal sphere
+    Simple,
+    // A diffuse sphere lit only by a glowing sphere, against a black background
+    Light,
+    // The Cornell box, two blocks in a red and green walled room lit from the ceiling
+    Cornell,
+}
+
+#[derive(Debug, Parser)]
+#[command(about = "Ray Tracing in One Weekend, renders a scene to an image file")]
+pub struct Args {
+    /// Image width in pixels
+    #[arg(short = 'W', long, default_value_t = 400, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+
+    /// Image height in pixels, defaults to width / aspect
+    #[arg(short = 'H', long, conflicts_with = "aspect", value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+
+    /// Aspect ratio as W:H or a single number, ignored when --height is given
+    #[arg(short, long, default_value = "16:9", value_parser = parse_aspect)]
+    pub aspect: f64,
+
+    /// Anti-aliasing samples per pixel
+    #[arg(short, long = "spp", default_value_t = 80, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples_per_pixel: u32,
+
+    /// Maximum number of times a ray may bounce
+    #[arg(short = 'd', long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..))]
+    pub max_depth: u8,
+
+    /// How light found by bouncing off materials and by sampling the lights are combined
+    #[arg(long, value_enum, default_value_t = Heuristic::Power)]
+    pub mis: Heuristic,
+
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+
+    /// Seed for the random number generator, makes renders reproducible
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Where to write the image, the format comes from the extension
+    /// (.png, .ppm, .bmp, or .exr, .pfm, .hdr for unclamped linear radiance), - for PPM on stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: PathBuf,
+
+    /// Bits per colour channel: 8 or 16 for .png and .ppm, 16 (half) or 32 (float) for .exr.
+    /// Defaults to 8 for LDR formats and 32 for HDR ones
+    #[arg(long, value_parser = parse_bit_depth)]
+    pub bit_depth: Option<u8>,
+
+    /// Built in scene to render
+    #[arg(long, value_enum, default_value_t = SceneChoice::Random)]
+    pub scene: SceneChoice,
+
+    /// TOML scene description to render instead of a built in scene
+    #[arg(short = 'f', long, conflicts_with = "scene")]
+    pub scene_file: Option<PathBuf>,
+
+    /// Overrides the scene's background: black, gradient, r,g,b or an .hdr or .pfm environment image
+    #[arg(short, long, value_parser = Background::parse)]
+    pub background: Option<Background>,
+}
+
+// Everything the render loop needs, resolved from the arguments
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub aspect_ratio: f64,
+    pub samples_per_pixel: u32,
+    pub max_depth: u8,
+    pub heuristic: Heuristic,
+    pub seed: Option<u64>,
+}
+
+impl Args {
+
+    // Exits with a usage error if the combination of arguments makes no sense
+    pub fn render_settings(&self) -> RenderSettings {
+        let height = match self.height {
+            Some(h) => h,
+            None => (self.width as f64 / self.aspect) as u32,
+        };
+        if height == 0 {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("a width of {} with an aspect ratio of {} gives an image with no rows", self.width, self.aspect),
+                )
+                .exit();
+        }
+
+        RenderSettings {
+            width: self.width,
+            height,
+            aspect_ratio: self.width as f64 / height as f64,
+            samples_per_pixel: self.samples_per_pixel,
+            max_depth: self.max_depth,
+            heuristic: self.mis,
+            seed: self.seed,
+        }
+    }
+
+    pub fn writes_to_stdout(&self) -> bool {
+        self.output.as_os_str() == "-"
+    }
+
+    // Checked before rendering, so a typo in the file name does not throw away a long render.
+    // Exits with a usage error if the format is unknown or cannot hold the bit depth.
+    pub fn output_format(&self) -> (ImageFormat, u8) {
+        let format = if self.writes_to_stdout() {
+            ImageFormat::Ppm
+        } else {
+            ImageFormat::from_path(&self.output)
+                .unwrap_or_else(|message| Args::command().error(ErrorKind::ValueValidation, message).exit())
+        };
+
+        let bit_depth = self.bit_depth.unwrap_or_else(|| format.default_bit_depth());
+        if !format.supports_bit_depth(bit_depth) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{:?} output does not support a bit depth of {}", format, bit_depth),
+                )
+                .exit();
+        }
+        (format, bit_depth)
+    }
+}
+
+// Accepts "16:9" or "1.7778"
+fn parse_aspect(s: &str) -> Result<f64, String> {
+    let aspect = match s.split_once(':') {
+        Some((w, h)) => {
+            let w: f64 = w.trim().parse().map_err(|_| format!("`{}` is not a number", w))?;
+            let h: f64 = h.trim().parse().map_err(|_| format!("`{}` is not a number", h))?;
+            w / h
+        }
+        None => s.trim().parse().map_err(|_| format!("`{}` is not a ratio like 16:9 or 1.78", s))?,
+    };
+
+    if !aspect.is_finite() || aspect <= 0.0 {
+        return Err(format!("`{}` must be a positive ratio", s));
+    }
+    Ok(aspect)
+}
+
+
+fn parse_bit_depth(s: &str) -> Result<u8, String> {
+    match s.trim() {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        "32" => Ok(32),
+        _ => Err(format!("`{}` is not a supported bit depth, expected 8, 16 or 32", s)),
+    }
+}
+
+
+// TEST
+#[test]
+fn test_parse_aspect() {
+    assert_eq!(parse_aspect("16:9"), Ok(16.0 / 9.0));
+    assert_eq!(parse_aspect("2"), Ok(2.0));
+    assert!(parse_aspect("16:0").is_err());
+    assert!(parse_aspect("-1").is_err());
+    assert!(parse_aspect("wide").is_err());
+}
+
+#[test]
+fn test_height_from_aspect() {
+    let args = Args::parse_from(["rt", "--width", "400", "--aspect", "2:1"]);
+    let settings = args.render_settings();
+    assert_eq!(settings.height, 200);
+
+    let args = Args::parse_from(["rt", "-W", "300", "-H", "300"]);
+    assert_eq!(args.render_settings().aspect_ratio, 1.0);
+
+    assert!(Args::try_parse_from(["rt", "--spp", "0"]).is_err());
+    assert!(Args::try_parse_from(["rt", "-H", "100", "--aspect", "4:3"]).is_err());
+    assert!(Args::try_parse_from(["rt", "--bit-depth", "12"]).is_err());
+}
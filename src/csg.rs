@@ -0,0 +1,149 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::aabb::*;
+use std::sync::Arc;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    // left with right cut away
+    Difference,
+}
+
+impl CsgOp {
+
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Constructive solid geometry, combining two closed shapes into a new one.
+// Walks the enter/exit intervals of both along the ray and keeps the crossings
+// where being inside the combination changes. Every surface keeps its own material,
+// so a difference shows the cutting shape's material on the cut faces.
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
+    bbox: Option<Aabb>,
+}
+
+impl Csg {
+
+    // Both sides must be closed shapes, i.e. give intervals
+    pub fn new(op: CsgOp, left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Csg {
+        let bbox = match (op, left.bounding_box(), right.bounding_box()) {
+            (CsgOp::Union, Some(l), Some(r)) => Some(Aabb::surrounding_box(l, r)),
+            (CsgOp::Intersection, Some(l), Some(r)) => {
+                // the overlap of the two boxes, squashed flat if they do not meet
+                let minimum = Point3 {
+                    x: l.minimum.x.max(r.minimum.x),
+                    y: l.minimum.y.max(r.minimum.y),
+                    z: l.minimum.z.max(r.minimum.z),
+                };
+                let maximum = Point3 {
+                    x: l.maximum.x.min(r.maximum.x).max(minimum.x),
+                    y: l.maximum.y.min(r.maximum.y).max(minimum.y),
+                    z: l.maximum.z.min(r.maximum.z).max(minimum.z),
+                };
+                Some(Aabb { minimum, maximum })
+            }
+            (CsgOp::Intersection, l, r) => l.or(r),
+            (CsgOp::Difference, l, _) => l,
+            _ => None,
+        };
+        Csg { op, left, right, bbox }
+    }
+}
+
+impl Hittable for Csg {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        if let Some(bbox) = self.bbox {
+            if !bbox.hit(ray, tmin, tmax) {
+                return None;
+            }
+        }
+        let intervals = self.intervals(ray)?;
+        intervals
+            .into_iter()
+            .flat_map(|(enter, exit)| vec![enter, exit])
+            .find(|hit| hit.t > tmin && hit.t < tmax)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        // (hit, from the left shape, entering it)
+        let mut crossings = Vec::new();
+        for (is_left, object) in [(true, &self.left), (false, &self.right)].iter() {
+            for (enter, exit) in object.intervals(ray).unwrap_or_default() {
+                crossings.push((enter, *is_left, true));
+                crossings.push((exit, *is_left, false));
+            }
+        }
+        crossings.sort_by(|a, b| a.0.t.partial_cmp(&b.0.t).unwrap());
+
+        let (mut in_left, mut in_right) = (false, false);
+        let mut boundaries = Vec::new();
+        for (mut hit, is_left, entering) in crossings {
+            let was_inside = self.op.inside(in_left, in_right);
+            if is_left { in_left = entering; } else { in_right = entering; }
+            let now_inside = self.op.inside(in_left, in_right);
+
+            if was_inside != now_inside {
+                // the normal already faces the ray, all that changes is whether this is a way in
+                hit.front_face = now_inside;
+                boundaries.push(hit);
+            }
+        }
+        Some(pair_crossings(boundaries))
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_csg_operations() {
+    use crate::sphere::Sphere;
+
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let ball = |x: f64| -> Arc<dyn Hittable> { Arc::new(Sphere { center: Point3 { x, y: 0.0, z: 0.0 }, radius: 1.0, mat_ptr: mat.clone() }) };
+    // two unit balls overlapping between x = -0.5 and 0.5
+    let ray = Ray::new(Point3 { x: -5.0, y: 0.0, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let ends = |csg: &Csg| -> Vec<(f64, bool)> {
+        csg.intervals(&ray).unwrap().into_iter().flat_map(|(a, b)| vec![(a.t, a.front_face), (b.t, b.front_face)]).collect()
+    };
+
+    let union = Csg::new(CsgOp::Union, ball(-0.5), ball(0.5));
+    assert_eq!(ends(&union), vec![(3.5, true), (6.5, false)]);
+
+    let intersection = Csg::new(CsgOp::Intersection, ball(-0.5), ball(0.5));
+    assert_eq!(ends(&intersection), vec![(4.5, true), (5.5, false)]);
+
+    let difference = Csg::new(CsgOp::Difference, ball(-0.5), ball(0.5));
+    assert_eq!(ends(&difference), vec![(3.5, true), (4.5, false)]);
+
+    // looking back from inside the cut, the first thing seen is the cut face, whose normal faces the ray
+    let back = Ray::new(Point3 { x: 5.0, y: 0.0, z: 0.0 }, Vec3 { x: -1.0, y: 0.0, z: 0.0 });
+    let hit = difference.hit(&back, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 5.5).abs() < 1e-12);
+    assert!(hit.front_face);
+    assert_eq!(hit.normal, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+
+    // the ray starting inside the union hits its far side from within
+    let inside = Ray::new(Point3::new(), Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let hit = union.hit(&inside, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 1.5).abs() < 1e-12);
+    assert!(!hit.front_face);
+}
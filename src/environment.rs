@@ -0,0 +1,226 @@
+use std::fmt;
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::vec3::*;
+use crate::image_io::*;
+use crate::random::*;
+
+
+// A piecewise constant density over [0, 1), one step per value of func
+struct Distribution1D {
+    func: Vec<f64>,
+    // cdf[i] is the chance of landing before step i, with one more entry of 1 at the end
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+
+    // All zero turns into even, as there is nothing better to go on
+    fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len() as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in func.iter() {
+            cdf.push(cdf[cdf.len() - 1] + f / n);
+        }
+        let integral = cdf[cdf.len() - 1];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 { *c / integral } else { i as f64 / n };
+        }
+        Distribution1D { func, cdf, integral }
+    }
+
+    // The step u falls in
+    fn step(&self, u: f64) -> usize {
+        let above = self.cdf.partition_point(|c| *c <= u);
+        above.clamp(1, self.func.len()) - 1
+    }
+
+    // A point in [0, 1), its density, and which step it is in
+    fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let i = self.step(u);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        let x = (i as f64 + offset) / self.func.len() as f64;
+        (x.min(1.0 - f64::EPSILON), self.pdf(i), i)
+    }
+
+    fn pdf(&self, i: usize) -> f64 {
+        if self.integral > 0.0 { self.func[i] / self.integral } else { 1.0 }
+    }
+}
+
+
+// A density over the unit square, a row is picked first and then a place along it
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+
+    fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = func.chunks(width).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+        debug_assert_eq!(rows.len(), height);
+        Distribution2D { rows, marginal }
+    }
+
+    // (u, v) and the density there
+    fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.rows[row].sample(u1);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        let width = self.rows[row].func.len();
+        let column = ((u * width as f64) as usize).min(width - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+
+// Light from every direction, from a latitude longitude (equirectangular) HDR image wrapped
+// around the scene, its top row straight up. rotation turns it about the vertical in degrees,
+// intensity scales its brightness. Directions are sampled in proportion to how bright the
+// image is there, so a small sun in a big sky is found almost every time.
+pub struct EnvironmentMap {
+    pub image: Image,
+    pub rotation: f64,
+    pub intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+
+    pub fn new(image: Image, rotation: f64, intensity: f64) -> Result<EnvironmentMap, String> {
+        let (width, height) = (image.width as usize, image.height as usize);
+        if width == 0 || height == 0 {
+            return Err("environment image is empty".to_string());
+        }
+        if let Some(bad) = image.pixels.iter().find(|c| !(c.x.is_finite() && c.y.is_finite() && c.z.is_finite())) {
+            return Err(format!("environment image holds a value that is not a number, {}", bad));
+        }
+
+        // rows near the poles cover less of the sphere, so they are picked less
+        let mut func = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let sin_theta = (PI * (row as f64 + 0.5) / height as f64).sin();
+            for pixel in image.pixels[row * width..(row + 1) * width].iter() {
+                func.push(luminance(*pixel).max(0.0) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&func, width, height);
+        Ok(EnvironmentMap { image, rotation, intensity, distribution })
+    }
+
+    pub fn load(path: &Path, rotation: f64, intensity: f64) -> Result<EnvironmentMap, String> {
+        EnvironmentMap::new(read_image(path)?, rotation, intensity).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // Where in the image (u across, v down) a world direction looks
+    fn uv_of(&self, direction: Vec3) -> (f64, f64) {
+        let d = rotate_y(direction.unit_vector(), -self.rotation);
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn direction_at(&self, u: f64, v: f64) -> Vec3 {
+        let (theta, phi) = (v * PI, u * 2.0 * PI - PI);
+        let d = Vec3 { x: theta.sin() * phi.cos(), y: theta.cos(), z: -theta.sin() * phi.sin() };
+        rotate_y(d, self.rotation)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Colour {
+        let (u, v) = self.uv_of(direction);
+        let (width, height) = (self.image.width as usize, self.image.height as usize);
+        let column = ((u * width as f64) as usize).min(width - 1);
+        let row = ((v * height as f64) as usize).min(height - 1);
+        self.intensity * self.image.pixels[row * width + column]
+    }
+
+    // A unit direction towards the bright parts of the map, and its density per unit of solid angle
+    pub fn sample(&self) -> (Vec3, f64) {
+        let ((u, v), pdf) = self.distribution.sample(random_double(), random_double());
+        let direction = self.direction_at(u, v);
+        (direction, solid_angle_pdf(pdf, v))
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        let (u, v) = self.uv_of(direction);
+        solid_angle_pdf(self.distribution.pdf(u, v), v)
+    }
+}
+
+// The image covers 2 pi by pi, squashed by sin(theta) towards the poles
+fn solid_angle_pdf(pdf_uv: f64, v: f64) -> f64 {
+    let sin_theta = (v * PI).sin();
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    pdf_uv / (2.0 * PI * PI * sin_theta)
+}
+
+fn rotate_y(v: Vec3, degrees: f64) -> Vec3 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vec3 { x: cos * v.x + sin * v.z, y: v.y, z: -sin * v.x + cos * v.z }
+}
+
+fn luminance(c: Colour) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Maps only equal themselves, comparing every pixel would be no use to anyone
+impl PartialEq for EnvironmentMap {
+    fn eq(&self, other: &EnvironmentMap) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EnvironmentMap({}x{}, rotation {}, intensity {})", self.image.width, self.image.height, self.rotation, self.intensity)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_environment_sampling() {
+    // dark but for one bright pixel, off the equator
+    let (width, height) = (16, 8);
+    let mut pixels = vec![Colour { x: 0.01, y: 0.01, z: 0.01 }; width * height];
+    pixels[2 * width + 5] = Colour { x: 100.0, y: 80.0, z: 60.0 };
+    let map = EnvironmentMap::new(Image { width: width as u32, height: height as u32, pixels }, 30.0, 2.0).unwrap();
+
+    let mut bright = 0;
+    for _ in 0..1000 {
+        let (direction, pdf) = map.sample();
+        assert!((pdf - map.pdf(direction)).abs() < 1e-6 * pdf);
+        if map.radiance(direction).x == 200.0 {
+            bright += 1;
+        }
+    }
+    // nearly every sample finds the bright pixel, rotation and all
+    assert!(bright > 950, "{}", bright);
+
+    // and the density over every direction adds up to 1
+    let (steps_theta, steps_phi) = (400, 800);
+    let (d_theta, d_phi) = (PI / steps_theta as f64, 2.0 * PI / steps_phi as f64);
+    let mut total = 0.0;
+    for i in 0..steps_theta {
+        let theta = (i as f64 + 0.5) * d_theta;
+        for j in 0..steps_phi {
+            let phi = (j as f64 + 0.5) * d_phi;
+            let direction = Vec3 { x: theta.sin() * phi.cos(), y: theta.cos(), z: theta.sin() * phi.sin() };
+            total += map.pdf(direction) * theta.sin() * d_theta * d_phi;
+        }
+    }
+    assert!((total - 1.0).abs() < 0.01, "{}", total);
+}
@@ -0,0 +1,244 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::*;
+use crate::triangle::intersect_triangle;
+use crate::image_io::Image;
+use crate::perlin::*;
+use std::sync::Arc;
+
+
+// A grid of heights between 0 and 1, nx samples along x by nz along z, x changing fastest
+pub struct HeightGrid {
+    pub nx: usize,
+    pub nz: usize,
+    pub heights: Vec<f64>,
+}
+
+impl HeightGrid {
+
+    // The brightness of each pixel, with the top row of the image at the far (+z) edge like a map.
+    // Height maps hold plain values rather than colours, so the gamma that reading applied is undone
+    pub fn from_image(image: &Image) -> HeightGrid {
+        let (nx, nz) = (image.width as usize, image.height as usize);
+        let mut heights = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            let row = nz - 1 - j;
+            for i in 0..nx {
+                let c = image.pixels[row * nx + i];
+                heights.push((c.x.sqrt() + c.y.sqrt() + c.z.sqrt()) / 3.0);
+            }
+        }
+        HeightGrid { nx, nz, heights }
+    }
+
+    // Rolling hills from fractal noise, scale is how many noise cells span the grid
+    pub fn from_noise(noise: &Perlin, scale: f64, octaves: u32, nx: usize, nz: usize) -> HeightGrid {
+        let mut heights = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                // off the lattice in y, where the noise would be flat
+                let p = Point3 { x: scale * i as f64 / nx as f64, y: 0.5, z: scale * j as f64 / nz as f64 };
+                heights.push((0.5 + 0.5 * noise.fbm(p, octaves, 2.0, 0.5)).clamp(0.0, 1.0));
+            }
+        }
+        HeightGrid { nx, nz, heights }
+    }
+
+    fn at(&self, i: usize, j: usize) -> f64 {
+        self.heights[i + self.nx * j]
+    }
+}
+
+
+// Terrain from a grid of heights, stretched over the box from min to min + size, where
+// a height of 1 reaches the top. Each cell between four samples is two triangles.
+// Rays walk the cells they pass over in order, so only a line of cells is tested rather than
+// the whole grid, and the first cell with a hit has the nearest one.
+pub struct Heightfield {
+    pub grid: HeightGrid,
+    pub min: Point3,
+    pub size: Vec3,
+    pub mat_ptr: Arc<dyn Material>,
+    // per sample, for smooth shading
+    normals: Vec<Vec3>,
+    bbox: Aabb,
+}
+
+impl Heightfield {
+
+    pub fn new(grid: HeightGrid, min: Point3, size: Vec3, mat_ptr: Arc<dyn Material>) -> Result<Heightfield, String> {
+        if grid.nx < 2 || grid.nz < 2 {
+            return Err(format!("heightfield needs at least 2x2 heights, got {}x{}", grid.nx, grid.nz));
+        }
+        if grid.heights.len() != grid.nx * grid.nz {
+            return Err(format!("heightfield is {}x{} but has {} heights", grid.nx, grid.nz, grid.heights.len()));
+        }
+        if size.x <= 0.0 || size.z <= 0.0 {
+            return Err("heightfield must have some width and depth".to_string());
+        }
+
+        // central differences, one sided at the edges
+        let (dx, dz) = (size.x / (grid.nx - 1) as f64, size.z / (grid.nz - 1) as f64);
+        let mut normals = Vec::with_capacity(grid.heights.len());
+        for j in 0..grid.nz {
+            for i in 0..grid.nx {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(grid.nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(grid.nz - 1));
+                let slope_x = (grid.at(i1, j) - grid.at(i0, j)) * size.y / ((i1 - i0) as f64 * dx);
+                let slope_z = (grid.at(i, j1) - grid.at(i, j0)) * size.y / ((j1 - j0) as f64 * dz);
+                normals.push(Vec3 { x: -slope_x, y: 1.0, z: -slope_z }.unit_vector());
+            }
+        }
+
+        let (low, high) = grid.heights.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), h| (lo.min(*h), hi.max(*h)));
+        let bbox = Aabb::new(
+            Point3 { x: min.x, y: min.y + low * size.y, z: min.z },
+            Point3 { x: min.x + size.x, y: min.y + high * size.y, z: min.z + size.z },
+        ).padded();
+
+        Ok(Heightfield { grid, min, size, mat_ptr, normals, bbox })
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (self.size.x / (self.grid.nx - 1) as f64, self.size.z / (self.grid.nz - 1) as f64)
+    }
+
+    fn point(&self, i: usize, j: usize) -> Point3 {
+        let (dx, dz) = self.cell_size();
+        Point3 { x: self.min.x + i as f64 * dx, y: self.min.y + self.grid.at(i, j) * self.size.y, z: self.min.z + j as f64 * dz }
+    }
+
+    // The nearer hit on the two triangles of cell (i, j)
+    fn hit_cell(&self, ray: &Ray, i: usize, j: usize, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let corners = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)];
+        // both wound to face up
+        let triangles = [[corners[0], corners[1], corners[2]], [corners[0], corners[2], corners[3]]];
+        let mut best: Option<(f64, [f64; 3], usize)> = None;
+        for (k, tri) in triangles.iter().enumerate() {
+            let points = [self.point(tri[0].0, tri[0].1), self.point(tri[1].0, tri[1].1), self.point(tri[2].0, tri[2].1)];
+            if let Some((t, b)) = intersect_triangle(ray, points) {
+                if t > tmin && t < tmax && best.is_none_or(|(nearest, _, _)| t < nearest) {
+                    best = Some((t, b, k));
+                }
+            }
+        }
+
+        let (t, b, k) = best?;
+        let tri = triangles[k];
+        let p = b[0] * self.point(tri[0].0, tri[0].1) + b[1] * self.point(tri[1].0, tri[1].1) + b[2] * self.point(tri[2].0, tri[2].1);
+        let normal_at = |(i, j): (usize, usize)| self.normals[i + self.grid.nx * j];
+        let outward_normal = (b[0] * normal_at(tri[0]) + b[1] * normal_at(tri[1]) + b[2] * normal_at(tri[2])).unit_vector();
+
+        // the real surface decides the side, as for meshes
+        let geometric_normal = (self.point(tri[1].0, tri[1].1) - self.point(tri[0].0, tri[0].1))
+            .cross(self.point(tri[2].0, tri[2].1) - self.point(tri[0].0, tri[0].1));
+        let front_face = ray.dir.dot(geometric_normal) < 0.0;
+
+        Some(HitRecord {
+            p,
+            normal: if front_face { outward_normal } else { -outward_normal },
+            mat_ptr: self.mat_ptr.clone(),
+            t,
+            // the whole grid maps onto the unit square, so one image drapes over it
+            u: (p.x - self.min.x) / self.size.x,
+            v: (p.z - self.min.z) / self.size.z,
+            front_face,
+            colour: None,
+            sampled_light: false,
+        })
+    }
+}
+
+impl Hittable for Heightfield {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let (start, end) = self.bbox.clip(ray, tmin, tmax)?;
+        let (dx, dz) = self.cell_size();
+        let (cells_x, cells_z) = (self.grid.nx - 1, self.grid.nz - 1);
+
+        // Amanatides and Woo's grid walk, over the x and z cells only
+        let entry = ray.clone().at(start);
+        let cell = |x: f64, size: f64, min: f64, cells: usize| (((x - min) / size).floor().max(0.0) as usize).min(cells - 1);
+        let (mut i, mut j) = (cell(entry.x, dx, self.min.x, cells_x), cell(entry.z, dz, self.min.z, cells_z));
+
+        // for each axis: which way to step, the t of the next cell boundary and the t across a cell
+        let axis = |dir: f64, orig: f64, min: f64, size: f64, index: usize| -> (isize, f64, f64) {
+            if dir > 0.0 {
+                (1, (min + (index + 1) as f64 * size - orig) / dir, size / dir)
+            } else if dir < 0.0 {
+                (-1, (min + index as f64 * size - orig) / dir, -size / dir)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) = axis(ray.dir.x, ray.orig.x, self.min.x, dx, i);
+        let (step_z, mut next_z, delta_z) = axis(ray.dir.z, ray.orig.z, self.min.z, dz, j);
+
+        let mut cell_start = start;
+        loop {
+            let cell_end = next_x.min(next_z).min(end);
+
+            // skip cells the ray passes wholly above or below
+            let heights = [self.grid.at(i, j), self.grid.at(i + 1, j), self.grid.at(i, j + 1), self.grid.at(i + 1, j + 1)];
+            let low = self.min.y + heights.iter().cloned().fold(f64::INFINITY, f64::min) * self.size.y;
+            let high = self.min.y + heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max) * self.size.y;
+            let (y0, y1) = (ray.orig.y + cell_start * ray.dir.y, ray.orig.y + cell_end * ray.dir.y);
+            if y0.max(y1) >= low - 1e-9 && y0.min(y1) <= high + 1e-9 {
+                if let Some(hit) = self.hit_cell(ray, i, j, tmin, tmax) {
+                    return Some(hit);
+                }
+            }
+
+            if cell_end >= end {
+                return None;
+            }
+            if next_x < next_z {
+                if (step_x < 0 && i == 0) || (step_x > 0 && i + 1 == cells_x) {
+                    return None;
+                }
+                i = (i as isize + step_x) as usize;
+                cell_start = next_x;
+                next_x += delta_x;
+            } else {
+                if (step_z < 0 && j == 0) || (step_z > 0 && j + 1 == cells_z) {
+                    return None;
+                }
+                j = (j as isize + step_z) as usize;
+                cell_start = next_z;
+                next_z += delta_z;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_heightfield_hit() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    // a ramp rising along x, from 0 to 1 over 3 samples
+    let grid = HeightGrid { nx: 3, nz: 2, heights: vec![0.0, 0.5, 1.0, 0.0, 0.5, 1.0] };
+    let field = Heightfield::new(grid, Point3::new(), Vec3 { x: 2.0, y: 2.0, z: 1.0 }, mat).unwrap();
+
+    // straight down onto the middle of the far cell, where the ramp is at 1.5
+    let down = Ray::new(Point3 { x: 1.5, y: 5.0, z: 0.5 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = field.hit(&down, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.p.y - 1.5).abs() < 1e-9);
+    assert!((hit.u - 0.75).abs() < 1e-9 && (hit.v - 0.5).abs() < 1e-9);
+    assert!(hit.front_face);
+    // the slope is 45 degrees
+    assert!((hit.normal - Vec3 { x: -1.0, y: 1.0, z: 0.0 }.unit_vector()).len() < 1e-9);
+
+    // flying level along x at a height of 0.9 it meets the ramp at x = 0.9, any higher than 2 it clears it
+    let across = Ray::new(Point3 { x: -1.0, y: 0.9, z: 0.5 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let hit = field.hit(&across, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.p.x - 0.9).abs() < 1e-9);
+    assert!(field.hit(&Ray::new(Point3 { x: -1.0, y: 2.5, z: 0.5 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 }), 0.001, f64::INFINITY).is_none());
+}
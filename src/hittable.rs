@@ -1,21 +1,30 @@
 use crate::vec3::*;
 use crate::ray::*;
 use crate::material::*;
-use std::rc::Rc;
+use crate::aabb::*;
+use crate::bvh::*;
+use crate::random::*;
 use std::sync::Arc;
 
 // #[derive(Debug,Copy,Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
-    pub mat_ptr: Arc<Material>,
+    pub mat_ptr: Arc<dyn Material>,
     pub t: f64,
+    // surface coordinates, for textures
+    pub u: f64,
+    pub v: f64,
     pub front_face:bool,
+    // interpolated vertex colour, for meshes that have them. Tints the material's albedo
+    pub colour: Option<Colour>,
+    // set on hits on the scene's lights, which light sampling could have found as well
+    pub sampled_light: bool,
 }
 
 
 impl HitRecord {
-    pub fn set_face_normal(mut self, ray:&Ray, outward_normal: Vec3){
+    pub fn set_face_normal(&mut self, ray:&Ray, outward_normal: Vec3){
         self.front_face = ray.dir.dot(outward_normal) < 0.0;
         if self.front_face {
             self.normal = outward_normal;
@@ -28,14 +37,86 @@ impl HitRecord {
 // #[derive(Send)]
 pub trait Hittable: Send + Sync  {
     fn hit(&self, ray: &Ray, tmin:f64, tmax:f64 ) -> Option<HitRecord>;
+
+    // None for objects that cannot be bounded, i.e. infinite planes
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // Every stretch of the ray (at any t, even behind its origin) that is inside the object,
+    // as the hits where it enters and leaves, in order. Only closed shapes have an inside,
+    // anything else gives None. CSG is built on this
+    fn intervals(&self, _ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        None
+    }
+
+    // For sampling lights: the density, per unit of solid angle, of random_direction picking
+    // direction from origin at the given time. 0 for objects that cannot be sampled
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    // A direction from origin towards a random point on the object, None for objects that cannot be sampled
+    fn random_direction(&self, _origin: Point3) -> Option<Vec3> {
+        None
+    }
+}
+
+// Wraps an object that is also in the scene's lights, so that hits on it say so.
+// A path that runs into it then knows light sampling could have found it as well
+pub struct SampledLight {
+    pub object: Arc<dyn Hittable>,
+}
+
+fn mark_light(mut hit: HitRecord) -> HitRecord {
+    hit.sampled_light = true;
+    hit
+}
+
+impl Hittable for SampledLight {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        self.object.hit(ray, tmin, tmax).map(mark_light)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let intervals = self.object.intervals(ray)?;
+        Some(intervals.into_iter().map(|(enter, exit)| (mark_light(enter), mark_light(exit))).collect())
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        self.object.pdf_value(origin, direction, time)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Option<Vec3> {
+        self.object.random_direction(origin)
+    }
+}
+
+// Sorts the places a ray crosses a closed surface and pairs them up into enter/exit intervals.
+// An odd count means the ray only grazed the surface somewhere, so nothing is inside.
+pub fn pair_crossings(mut hits: Vec<HitRecord>) -> Vec<(HitRecord, HitRecord)> {
+    if !hits.len().is_multiple_of(2) {
+        return Vec::new();
+    }
+    hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+    let mut intervals = Vec::with_capacity(hits.len() / 2);
+    let mut hits = hits.into_iter();
+    while let (Some(enter), Some(exit)) = (hits.next(), hits.next()) {
+        intervals.push((enter, exit));
+    }
+    intervals
 }
 
 
 
-// impl 
+// impl
 //
+#[derive(Default)]
 pub struct HittableList {
-    objects: Vec<Arc<Hittable>>
+    objects: Vec<Arc<dyn Hittable>>
 }
 
 impl HittableList{
@@ -46,13 +127,38 @@ impl HittableList{
         }
     }
 
-    pub fn clear(mut self){
+    pub fn clear(&mut self){
         self.objects = Vec::new();
     }
 
-    pub fn add(&mut self, sharedptr:Arc<Hittable> ){
+    pub fn add(&mut self, sharedptr:Arc<dyn Hittable> ){
         self.objects.push(sharedptr);
     }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // Moves every bounded object into a single BVH,
+    // anything without a bounding box is still tested linearly alongside it
+    pub fn into_bvh(self) -> HittableList {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = self.objects
+            .into_iter()
+            .partition(|object| object.bounding_box().is_some());
+
+        let mut list = HittableList::new();
+        if !bounded.is_empty() {
+            list.add(Arc::new(BvhNode::new(bounded)));
+        }
+        for object in unbounded {
+            list.add(object);
+        }
+        list
+    }
 }
 
 impl Hittable for HittableList{
@@ -61,15 +167,44 @@ impl Hittable for HittableList{
     fn hit(&self,ray:&Ray, tmin:f64, tmax:f64, ) -> Option<HitRecord> {
         let mut hit_anything = None;
         let mut closest_so_far = tmax;
-        
+
         for object in self.objects.iter() {
-            if let Some(hit) = object.hit(&ray, tmin, closest_so_far) {
-                
+            if let Some(hit) = object.hit(ray, tmin, closest_so_far) {
+
                 closest_so_far = hit.t;
                 hit_anything = Some(hit);
             }
         }
-        return hit_anything;
+        hit_anything
+    }
+
+    // Sampling a list picks one of its objects at random, so the density is their average
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.objects.iter().map(|object| object.pdf_value(origin, direction, time)).sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random_direction(&self, origin: Point3) -> Option<Vec3> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let i = ((random_double() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        self.objects[i].random_direction(origin)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut output_box: Option<Aabb> = None;
+        for object in self.objects.iter() {
+            let bbox = object.bounding_box()?;
+            output_box = match output_box {
+                Some(b) => Some(Aabb::surrounding_box(b, bbox)),
+                None => Some(bbox),
+            };
+        }
+        output_box
     }
 
 }
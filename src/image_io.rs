@@ -0,0 +1,810 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+
+use crate::vec3::*;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    // binary P6
+    Ppm,
+    Png,
+    // 24 bit uncompressed
+    Bmp,
+    // OpenEXR, uncompressed scanlines of half or float
+    Exr,
+    // Portable Float Map
+    Pfm,
+    // Radiance RGBE
+    Hdr,
+}
+
+impl ImageFormat {
+
+    pub fn from_path(path: &Path) -> Result<ImageFormat, String> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("png") => Ok(ImageFormat::Png),
+            Some("bmp") => Ok(ImageFormat::Bmp),
+            Some("exr") => Ok(ImageFormat::Exr),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            Some(ext) => Err(format!("unsupported image format `.{}`, expected .ppm, .png, .bmp, .exr, .pfm or .hdr", ext)),
+            None => Err(format!("cannot tell the image format of `{}` without a file extension", path.display())),
+        }
+    }
+
+    pub fn supports_bit_depth(self, bit_depth: u8) -> bool {
+        match self {
+            ImageFormat::Ppm | ImageFormat::Png => bit_depth == 8 || bit_depth == 16,
+            ImageFormat::Bmp => bit_depth == 8,
+            ImageFormat::Exr => bit_depth == 16 || bit_depth == 32,
+            // RGBE is 8 bits of mantissa per channel sharing an exponent, 32 bits a pixel
+            ImageFormat::Pfm | ImageFormat::Hdr => bit_depth == 32,
+        }
+    }
+
+    pub fn default_bit_depth(self) -> u8 {
+        match self {
+            ImageFormat::Ppm | ImageFormat::Png | ImageFormat::Bmp => 8,
+            ImageFormat::Exr | ImageFormat::Pfm | ImageFormat::Hdr => 32,
+        }
+    }
+}
+
+
+// A rendered frame, pixels are linear radiance (already averaged over the samples),
+// stored row by row starting from the top left.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Colour>,
+}
+
+impl Image {
+
+    // Turns the per pixel sums of every sample into averages
+    pub fn from_samples(width: u32, height: u32, sums: Vec<Colour>, samples_per_pixel: u32) -> Image {
+        assert_eq!(sums.len(), width as usize * height as usize);
+        let scale = 1.0 / samples_per_pixel as f64;
+        Image {
+            width,
+            height,
+            pixels: sums.into_iter().map(|c| c * scale).collect(),
+        }
+    }
+
+    // Gamma 2 corrected and clamped to 0..1, ready for an LDR format
+    fn display_values(&self) -> impl Iterator<Item = [f64; 3]> + '_ {
+        self.pixels.iter().map(|c| {
+            [
+                f64::sqrt(c.x.clamp(0.0, 1.0)),
+                f64::sqrt(c.y.clamp(0.0, 1.0)),
+                f64::sqrt(c.z.clamp(0.0, 1.0)),
+            ]
+        })
+    }
+
+    fn to_rgb8(&self) -> Vec<u8> {
+        self.display_values()
+            .flat_map(|rgb| rgb.map(|v| (256.0 * v.min(0.999)) as u8))
+            .collect()
+    }
+
+    // Big endian, which is what both PNG and PPM want
+    fn to_rgb16_be(&self) -> Vec<u8> {
+        self.display_values()
+            .flat_map(|rgb| rgb.map(|v| (65535.0 * v).round() as u16))
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+}
+
+
+pub fn write_image(out: &mut impl Write, image: &Image, format: ImageFormat, bit_depth: u8) -> io::Result<()> {
+    if !format.supports_bit_depth(bit_depth) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} images cannot be written with {} bits per channel", format, bit_depth),
+        ));
+    }
+
+    match format {
+        ImageFormat::Ppm => write_ppm(out, image, bit_depth),
+        ImageFormat::Png => write_png(out, image, bit_depth),
+        ImageFormat::Bmp => write_bmp(out, image),
+        ImageFormat::Exr => write_exr(out, image, bit_depth),
+        ImageFormat::Pfm => write_pfm(out, image),
+        ImageFormat::Hdr => write_hdr(out, image),
+    }?;
+    out.flush()
+}
+
+fn write_ppm(out: &mut impl Write, image: &Image, bit_depth: u8) -> io::Result<()> {
+    if bit_depth == 16 {
+        write!(out, "P6\n{} {}\n65535\n", image.width, image.height)?;
+        out.write_all(&image.to_rgb16_be())
+    } else {
+        write!(out, "P6\n{} {}\n255\n", image.width, image.height)?;
+        out.write_all(&image.to_rgb8())
+    }
+}
+
+fn write_png(out: &mut impl Write, image: &Image, bit_depth: u8) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(out, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgb);
+
+    let data = if bit_depth == 16 {
+        encoder.set_depth(png::BitDepth::Sixteen);
+        image.to_rgb16_be()
+    } else {
+        encoder.set_depth(png::BitDepth::Eight);
+        image.to_rgb8()
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn write_bmp(out: &mut impl Write, image: &Image) -> io::Result<()> {
+    // rows are stored bottom up in BGR order, each padded out to a multiple of 4 bytes
+    let row_size = (image.width as usize * 3 + 3) & !3;
+    let pixel_bytes = row_size * image.height as usize;
+    let header_size = 14 + 40;
+
+    // BITMAPFILEHEADER
+    out.write_all(b"BM")?;
+    out.write_all(&((header_size + pixel_bytes) as u32).to_le_bytes())?;
+    out.write_all(&[0; 4])?;
+    out.write_all(&(header_size as u32).to_le_bytes())?;
+
+    // BITMAPINFOHEADER
+    out.write_all(&40u32.to_le_bytes())?;
+    out.write_all(&(image.width as i32).to_le_bytes())?;
+    out.write_all(&(image.height as i32).to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // colour planes
+    out.write_all(&24u16.to_le_bytes())?; // bits per pixel
+    out.write_all(&0u32.to_le_bytes())?; // no compression
+    out.write_all(&(pixel_bytes as u32).to_le_bytes())?;
+    out.write_all(&2835i32.to_le_bytes())?; // 72 DPI, in pixels per metre
+    out.write_all(&2835i32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?; // palette size
+    out.write_all(&0u32.to_le_bytes())?; // important colours
+
+    let rgb = image.to_rgb8();
+    let mut row = vec![0u8; row_size];
+    for y in (0..image.height as usize).rev() {
+        let start = y * image.width as usize * 3;
+        for (x, pixel) in rgb[start..start + image.width as usize * 3].chunks(3).enumerate() {
+            row[x * 3] = pixel[2];
+            row[x * 3 + 1] = pixel[1];
+            row[x * 3 + 2] = pixel[0];
+        }
+        out.write_all(&row)?;
+    }
+    Ok(())
+}
+
+
+// The simplest OpenEXR file there is, a single part of uncompressed scanlines.
+// Half is 16 bits per channel, float is 32.
+fn write_exr(out: &mut impl Write, image: &Image, bit_depth: u8) -> io::Result<()> {
+    let (pixel_type, bytes_per_channel): (i32, usize) = if bit_depth == 16 { (1, 2) } else { (2, 4) };
+
+    fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    }
+
+    // channels have to be listed in alphabetical order, and are stored in that order too
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"].iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&pixel_type.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        channels.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        channels.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    channels.push(0);
+
+    let mut window = Vec::new();
+    for v in [0, 0, image.width as i32 - 1, image.height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630u32.to_le_bytes()); // magic number
+    header.extend_from_slice(&2u32.to_le_bytes()); // version 2, single part scanline
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]); // increasing y
+    attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+    out.write_all(&header)?;
+
+    // offset table, one entry per scanline pointing at its chunk
+    let line_bytes = image.width as usize * 3 * bytes_per_channel;
+    let chunk_size = 8 + line_bytes;
+    let first_chunk = header.len() + 8 * image.height as usize;
+    for y in 0..image.height as usize {
+        out.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_bytes);
+    for (y, row) in image.pixels.chunks(image.width as usize).enumerate() {
+        line.clear();
+        for channel in [2, 1, 0].iter() {
+            for pixel in row {
+                let value = pixel[*channel] as f32;
+                if bit_depth == 16 {
+                    line.extend_from_slice(&f32_to_f16(value).to_le_bytes());
+                } else {
+                    line.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_bytes as i32).to_le_bytes())?;
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+// IEEE half precision, rounding to nearest even
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinity, NaN stays NaN
+        let nan_bit = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan_bit;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        // too big, becomes infinity
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        // subnormal half, or too small and flushed to zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let full_mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let mut half = full_mantissa >> shift;
+        let remainder = full_mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+            half += 1;
+        }
+        return sign | half as u16;
+    }
+
+    let mut half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    // a carry out of the mantissa correctly bumps the exponent, up to infinity
+    if remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1) {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+// Little endian floats (the negative scale says so), rows stored bottom up
+fn write_pfm(out: &mut impl Write, image: &Image) -> io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    for row in image.pixels.chunks(image.width as usize).rev() {
+        for pixel in row {
+            out.write_all(&(pixel.x as f32).to_le_bytes())?;
+            out.write_all(&(pixel.y as f32).to_le_bytes())?;
+            out.write_all(&(pixel.z as f32).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// Radiance .hdr, with flat (not run length encoded) scanlines
+fn write_hdr(out: &mut impl Write, image: &Image) -> io::Result<()> {
+    write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height, image.width)?;
+    let rgbe: Vec<u8> = image.pixels.iter().flat_map(|c| colour_to_rgbe(*c)).collect();
+    out.write_all(&rgbe)
+}
+
+// The brightest channel picks a shared exponent, every channel keeps 8 bits of mantissa
+fn colour_to_rgbe(colour: Colour) -> [u8; 4] {
+    let r = colour.x.max(0.0);
+    let g = colour.y.max(0.0);
+    let b = colour.z.max(0.0);
+    let brightest = r.max(g).max(b);
+    if brightest < 1e-32 || !brightest.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // brightest = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    let mut mantissa = brightest / 2f64.powi(exponent);
+    if mantissa >= 1.0 {
+        mantissa /= 2.0;
+        exponent += 1;
+    }
+    let scale = mantissa * 256.0 / brightest;
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+
+// Loads an image for use as a texture or environment.
+// LDR formats are stored gamma 2 encoded, so they are squared back into linear values,
+// the opposite of what writing does. HDR formats already hold linear values.
+pub fn read_image(path: &Path) -> Result<Image, String> {
+    let format = ImageFormat::from_path(path)?;
+    let image = match format {
+        ImageFormat::Png => {
+            let file = File::open(path).map_err(|err| format!("could not open {}: {}", path.display(), err))?;
+            read_png(BufReader::new(file))
+        }
+        ImageFormat::Ppm | ImageFormat::Hdr | ImageFormat::Pfm => {
+            let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+            match format {
+                ImageFormat::Ppm => read_ppm(&bytes),
+                ImageFormat::Hdr => read_hdr(&bytes),
+                _ => read_pfm(&bytes),
+            }
+        }
+        _ => Err(format!("reading {:?} images is not supported", format)),
+    };
+    image.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn srgb_to_linear(v: f64) -> f64 {
+    v * v
+}
+
+fn read_png(input: impl io::Read) -> Result<Image, String> {
+    let mut decoder = png::Decoder::new(input);
+    // palettes become rgb and low bit depths get widened to 8 bits
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|err| err.to_string())?;
+    let bytes = &buf[..info.buffer_size()];
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err("palette was not expanded".to_string()),
+    };
+    let samples: Vec<f64> = match info.bit_depth {
+        png::BitDepth::Sixteen => bytes
+            .chunks(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.0)
+            .collect(),
+        _ => bytes.iter().map(|b| *b as f64 / 255.0).collect(),
+    };
+
+    // alpha is dropped, grey is copied into every channel
+    let pixels = samples
+        .chunks(channels)
+        .map(|px| {
+            let (r, g, b) = if channels < 3 { (px[0], px[0], px[0]) } else { (px[0], px[1], px[2]) };
+            Colour { x: srgb_to_linear(r), y: srgb_to_linear(g), z: srgb_to_linear(b) }
+        })
+        .collect();
+
+    Ok(Image { width: info.width, height: info.height, pixels })
+}
+
+// Both the ASCII (P3) and binary (P6) flavours
+fn read_ppm(bytes: &[u8]) -> Result<Image, String> {
+    let mut pos = 0;
+
+    // whitespace separated header fields, with # comments running to the end of the line
+    let next_token = |pos: &mut usize| -> Result<String, String> {
+        loop {
+            while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+                *pos += 1;
+            }
+            if *pos < bytes.len() && bytes[*pos] == b'#' {
+                while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                    *pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = *pos;
+        while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if start == *pos {
+            return Err("unexpected end of file".to_string());
+        }
+        Ok(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+    };
+    let number = |pos: &mut usize, what: &str| -> Result<u32, String> {
+        let token = next_token(pos)?;
+        token.parse::<u32>().map_err(|_| format!("bad {} `{}`", what, token))
+    };
+
+    let magic = next_token(&mut pos)?;
+    let width = number(&mut pos, "width")?;
+    let height = number(&mut pos, "height")?;
+    let max_value = number(&mut pos, "maximum value")?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(format!("bad maximum value {}", max_value));
+    }
+    let too_big = || format!("{}x{} is too big", width, height);
+    let count = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(3)).ok_or_else(too_big)?;
+
+    let samples: Vec<u32> = match magic.as_str() {
+        "P3" => (0..count).map(|_| number(&mut pos, "sample")).collect::<Result<_, _>>()?,
+        "P6" => {
+            // exactly one whitespace character between the header and the data
+            pos += 1;
+            let wide = max_value > 255;
+            let needed = if wide { count.checked_mul(2).ok_or_else(too_big)? } else { count };
+            let end = pos.checked_add(needed).ok_or_else(too_big)?;
+            let data = bytes.get(pos..end).ok_or("file is shorter than its header says")?;
+            if wide {
+                data.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32).collect()
+            } else {
+                data.iter().map(|b| *b as u32).collect()
+            }
+        }
+        _ => return Err(format!("`{}` is not a PPM file, expected P3 or P6", magic)),
+    };
+
+    let scale = 1.0 / max_value as f64;
+    let pixels = samples
+        .chunks(3)
+        .map(|px| Colour {
+            x: srgb_to_linear(px[0] as f64 * scale),
+            y: srgb_to_linear(px[1] as f64 * scale),
+            z: srgb_to_linear(px[2] as f64 * scale),
+        })
+        .collect();
+    Ok(Image { width, height, pixels })
+}
+
+
+// Radiance .hdr, in the usual -Y h +X w orientation. Scanlines may be flat or run length encoded
+fn read_hdr(bytes: &[u8]) -> Result<Image, String> {
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> Result<String, String> {
+        let end = bytes[*pos..].iter().position(|b| *b == b'\n').ok_or("unexpected end of header")?;
+        let line = String::from_utf8_lossy(&bytes[*pos..*pos + end]).into_owned();
+        *pos += end + 1;
+        Ok(line)
+    };
+
+    if !next_line(&mut pos)?.starts_with("#?") {
+        return Err("not a Radiance HDR file".to_string());
+    }
+    // variables like FORMAT and EXPOSURE, up to a blank line
+    loop {
+        let line = next_line(&mut pos)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported pixel format `{}`, expected 32-bit_rle_rgbe", format.trim()));
+            }
+        }
+    }
+    let resolution = next_line(&mut pos)?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<u32>().map_err(|_| format!("bad height `{}`", h))?,
+            w.parse::<u32>().map_err(|_| format!("bad width `{}`", w))?,
+        ),
+        _ => return Err(format!("unsupported orientation `{}`, expected -Y <height> +X <width>", resolution)),
+    };
+
+    if width == 0 || height == 0 {
+        return Err(format!("{}x{} image has no pixels", width, height));
+    }
+    // run length encoding packs at most 127 values into 2 bytes, so even then a pixel takes up
+    // more than a sixteenth of a byte, which bounds how big an image the rest of the file can be
+    let w = width as usize;
+    let too_big = || format!("{}x{} is too big", width, height);
+    let count = w.checked_mul(height as usize).ok_or_else(too_big)?;
+    if count / 16 > bytes.len() - pos {
+        return Err(too_big());
+    }
+    let mut pixels = Vec::with_capacity(count);
+    let mut scanline = vec![0u8; w.checked_mul(4).ok_or_else(too_big)?];
+    let short = || "file is shorter than its header says".to_string();
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&w)
+            && bytes.len() >= pos + 4
+            && bytes[pos] == 2 && bytes[pos + 1] == 2
+            && ((bytes[pos + 2] as usize) << 8 | bytes[pos + 3] as usize) == w;
+        if rle {
+            pos += 4;
+            // each channel of the whole line in turn, as runs (count > 128) or literal stretches
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < w {
+                    let count = *bytes.get(pos).ok_or_else(short)? as usize;
+                    pos += 1;
+                    let (length, repeat) = if count > 128 { (count - 128, true) } else { (count, false) };
+                    if length == 0 || x + length > w {
+                        return Err("bad run length in scanline".to_string());
+                    }
+                    for i in 0..length {
+                        let value = if repeat { bytes.get(pos) } else { bytes.get(pos + i) };
+                        scanline[(x + i) * 4 + channel] = *value.ok_or_else(short)?;
+                    }
+                    pos += if repeat { 1 } else { length };
+                    x += length;
+                }
+            }
+        } else {
+            let flat = bytes.get(pos..pos + w * 4).ok_or_else(short)?;
+            scanline.copy_from_slice(flat);
+            pos += w * 4;
+        }
+        pixels.extend(scanline.chunks(4).map(|rgbe| rgbe_to_colour([rgbe[0], rgbe[1], rgbe[2], rgbe[3]])));
+    }
+    Ok(Image { width, height, pixels })
+}
+
+fn rgbe_to_colour(rgbe: [u8; 4]) -> Colour {
+    if rgbe[3] == 0 {
+        return Colour::new();
+    }
+    let scale = 2f64.powi(rgbe[3] as i32 - 128 - 8);
+    Colour {
+        x: (rgbe[0] as f64 + 0.5) * scale,
+        y: (rgbe[1] as f64 + 0.5) * scale,
+        z: (rgbe[2] as f64 + 0.5) * scale,
+    }
+}
+
+// Colour (PF) or grey (Pf) floats, little endian when the scale is negative, rows stored bottom up
+fn read_pfm(bytes: &[u8]) -> Result<Image, String> {
+    // the header is three whitespace separated lines: kind, size and scale
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    while fields.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("unexpected end of header".to_string());
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    // exactly one whitespace character between the header and the data
+    pos += 1;
+
+    let channels = match fields[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(format!("`{}` is not a PFM file, expected PF or Pf", magic)),
+    };
+    let width = fields[1].parse::<u32>().map_err(|_| format!("bad width `{}`", fields[1]))?;
+    let height = fields[2].parse::<u32>().map_err(|_| format!("bad height `{}`", fields[2]))?;
+    let scale = fields[3].parse::<f64>().map_err(|_| format!("bad scale `{}`", fields[3]))?;
+    if width == 0 || height == 0 {
+        return Err(format!("{}x{} image has no pixels", width, height));
+    }
+
+    let too_big = || format!("{}x{} is too big", width, height);
+    let length = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(channels))
+        .and_then(|n| n.checked_mul(4))
+        .ok_or_else(too_big)?;
+    let end = pos.checked_add(length).ok_or_else(too_big)?;
+    let data = bytes.get(pos..end).ok_or("file is shorter than its header says")?;
+    let values: Vec<f64> = data
+        .chunks(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            (if scale < 0.0 { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }) as f64
+        })
+        .collect();
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for row in values.chunks(width as usize * channels).rev() {
+        pixels.extend(row.chunks(channels).map(|px| {
+            if channels == 1 { Colour { x: px[0], y: px[0], z: px[0] } } else { Colour { x: px[0], y: px[1], z: px[2] } }
+        }));
+    }
+    Ok(Image { width, height, pixels })
+}
+
+
+// TEST
+#[cfg(test)]
+fn test_image() -> Image {
+    Image {
+        width: 3,
+        height: 2,
+        pixels: vec![
+            Colour { x: 1.0, y: 0.0, z: 0.0 }, Colour { x: 0.0, y: 1.0, z: 0.0 }, Colour { x: 0.0, y: 0.0, z: 1.0 },
+            Colour { x: 0.25, y: 0.25, z: 0.25 }, Colour { x: 4.0, y: 4.0, z: 4.0 }, Colour { x: -1.0, y: 0.0, z: 0.0 },
+        ],
+    }
+}
+
+#[test]
+fn test_format_from_extension() {
+    assert_eq!(ImageFormat::from_path(Path::new("out.PNG")), Ok(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path(Path::new("a/b.ppm")), Ok(ImageFormat::Ppm));
+    assert!(ImageFormat::from_path(Path::new("out.jpg")).is_err());
+    assert!(ImageFormat::from_path(Path::new("out")).is_err());
+    assert!(!ImageFormat::Bmp.supports_bit_depth(16));
+}
+
+#[test]
+fn test_write_ppm() {
+    let mut out = Vec::new();
+    write_image(&mut out, &test_image(), ImageFormat::Ppm, 8).unwrap();
+    let header = b"P6\n3 2\n255\n";
+    assert_eq!(&out[..header.len()], header);
+    // gamma 2: 0.25 becomes 0.5, out of range values are clamped
+    assert_eq!(&out[header.len()..], &[255, 0, 0, 0, 255, 0, 0, 0, 255, 128, 128, 128, 255, 255, 255, 0, 0, 0]);
+}
+
+#[test]
+fn test_write_bmp_row_padding() {
+    let mut out = Vec::new();
+    write_image(&mut out, &test_image(), ImageFormat::Bmp, 8).unwrap();
+    // 3 pixels is 9 bytes a row, padded to 12
+    assert_eq!(out.len(), 54 + 12 * 2);
+    // bottom row comes first, in BGR order
+    assert_eq!(&out[54..57], &[128, 128, 128]);
+    assert_eq!(&out[66..69], &[0, 0, 255]);
+}
+
+#[test]
+fn test_f32_to_f16() {
+    assert_eq!(f32_to_f16(0.0), 0x0000);
+    assert_eq!(f32_to_f16(-0.0), 0x8000);
+    assert_eq!(f32_to_f16(1.0), 0x3c00);
+    assert_eq!(f32_to_f16(0.5), 0x3800);
+    assert_eq!(f32_to_f16(-2.0), 0xc000);
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16(1e6), 0x7c00);
+    // smallest subnormal half
+    assert_eq!(f32_to_f16(5.960_464_5e-8), 0x0001);
+    assert_eq!(f32_to_f16(f32::NAN) & 0x7c00, 0x7c00);
+}
+
+#[test]
+fn test_colour_to_rgbe() {
+    assert_eq!(colour_to_rgbe(Colour { x: 1.0, y: 1.0, z: 1.0 }), [128, 128, 128, 129]);
+    assert_eq!(colour_to_rgbe(Colour { x: 0.0, y: 0.0, z: 0.0 }), [0, 0, 0, 0]);
+    // values above 1 survive, which is the point of the format
+    let rgbe = colour_to_rgbe(Colour { x: 10.0, y: 5.0, z: 0.0 });
+    let scale = 2f64.powi(rgbe[3] as i32 - 128 - 8);
+    assert!((rgbe[0] as f64 * scale - 10.0).abs() < 0.1);
+    assert!((rgbe[1] as f64 * scale - 5.0).abs() < 0.1);
+}
+
+#[test]
+fn test_write_hdr_formats_keep_range() {
+    let mut out = Vec::new();
+    write_image(&mut out, &test_image(), ImageFormat::Pfm, 32).unwrap();
+    let header = b"PF\n3 2\n-1.0\n";
+    assert_eq!(&out[..header.len()], header);
+    // bottom row first, so the 4.0 pixel is the second one written
+    let value = f32::from_le_bytes([out[header.len() + 12], out[header.len() + 13], out[header.len() + 14], out[header.len() + 15]]);
+    assert_eq!(value, 4.0);
+
+    let mut out = Vec::new();
+    write_image(&mut out, &test_image(), ImageFormat::Exr, 16).unwrap();
+    assert_eq!(&out[..4], &[0x76, 0x2f, 0x31, 0x01]);
+    // header + offsets + 2 lines of (y, size, 3 channels * 3 pixels * 2 bytes)
+    let header_end = out.len() - 2 * 8 - 2 * (8 + 18);
+    let first_offset = u64::from_le_bytes([out[header_end], out[header_end + 1], out[header_end + 2], out[header_end + 3], out[header_end + 4], out[header_end + 5], out[header_end + 6], out[header_end + 7]]);
+    assert_eq!(first_offset as usize, header_end + 16);
+
+    assert!(write_image(&mut Vec::new(), &test_image(), ImageFormat::Hdr, 8).is_err());
+}
+
+#[test]
+fn test_write_png_signature() {
+    let mut out = Vec::new();
+    write_image(&mut out, &test_image(), ImageFormat::Png, 16).unwrap();
+    assert_eq!(&out[..8], &[137, 80, 78, 71, 13, 10, 26, 10]);
+}
+
+#[test]
+fn test_read_back_written_images() {
+    // writing clamps, so only compare the in range pixels
+    let original = test_image();
+    let close = |a: Colour, b: Colour, eps: f64| (a - b).len() < eps;
+
+    let mut png_bytes = Vec::new();
+    write_image(&mut png_bytes, &original, ImageFormat::Png, 16).unwrap();
+    let png = read_png(&png_bytes[..]).unwrap();
+    assert_eq!((png.width, png.height), (3, 2));
+    for i in 0..4 {
+        assert!(close(png.pixels[i], original.pixels[i], 1e-4), "{} {}", png.pixels[i], original.pixels[i]);
+    }
+
+    let mut ppm_bytes = Vec::new();
+    write_image(&mut ppm_bytes, &original, ImageFormat::Ppm, 8).unwrap();
+    let ppm = read_ppm(&ppm_bytes).unwrap();
+    for i in 0..4 {
+        assert!(close(ppm.pixels[i], original.pixels[i], 1e-2));
+    }
+
+    let ascii = read_ppm(b"P3\n# a comment\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+    assert_eq!(ascii.pixels, vec![Colour { x: 1.0, y: 0.0, z: 0.0 }, Colour { x: 0.0, y: 0.0, z: 1.0 }]);
+    assert!(read_ppm(b"P6\n2 2\n255\n").is_err());
+    assert!(read_ppm(b"P6\n4294967295 4294967295\n65535\n").is_err());
+}
+
+#[test]
+fn test_read_hdr_formats() {
+    let original = test_image();
+    let relative = |a: Colour, b: Colour, eps: f64| (a - b).len() <= eps * b.len().max(1e-3);
+
+    let mut pfm_bytes = Vec::new();
+    write_image(&mut pfm_bytes, &original, ImageFormat::Pfm, 32).unwrap();
+    let pfm = read_pfm(&pfm_bytes).unwrap();
+    assert_eq!((pfm.width, pfm.height), (3, 2));
+    for (read, written) in pfm.pixels.iter().zip(original.pixels.iter()) {
+        assert!(relative(*read, *written, 1e-6));
+    }
+    // a header too big to even count the bytes of is an error, not a panic
+    assert!(read_pfm(b"PF\n4294967295 4294967295\n-1.0\n").is_err());
+    assert!(read_pfm(b"PF\n0 1\n-1.0\n").is_err());
+
+    let mut hdr_bytes = Vec::new();
+    write_image(&mut hdr_bytes, &original, ImageFormat::Hdr, 32).unwrap();
+    let hdr = read_hdr(&hdr_bytes).unwrap();
+    // RGBE has no sign, so negative values come back as 0
+    for (read, written) in hdr.pixels.iter().zip(original.pixels.iter()) {
+        let written = Colour { x: written.x.max(0.0), y: written.y.max(0.0), z: written.z.max(0.0) };
+        assert!(relative(*read, written, 0.02), "{} {}", read, written);
+    }
+
+    // one run length encoded line of 8: red all one run, green given literally, blue and exponent runs
+    let mut rle = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+    rle.extend_from_slice(&[2, 2, 0, 8, 136, 127, 8, 0, 16, 32, 48, 64, 80, 96, 112, 136, 0, 136, 129]);
+    let line = read_hdr(&rle).unwrap();
+    assert_eq!(line.pixels.len(), 8);
+    assert_eq!(line.pixels[3], Colour { x: 127.5 / 128.0, y: 48.5 / 128.0, z: 0.5 / 128.0 });
+    assert!(read_hdr(&rle[..rle.len() - 1]).is_err());
+
+    // sizes the file could never hold are an error, not a panic
+    assert!(read_hdr(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4000000000 +X 4000000000\n").is_err());
+    assert!(read_hdr(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 0 +X 8\n").is_err());
+}
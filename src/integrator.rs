@@ -0,0 +1,205 @@
+use clap::ValueEnum;
+
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::scenes::Scene;
+
+
+// How light found by sampling the material and light found by sampling the lights are
+// weighted against each other, so each counts most where it is the better way to find it
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Heuristic {
+    // in proportion to the pdfs
+    Balance,
+    // in proportion to the squared pdfs, which favours the better one more strongly
+    Power,
+}
+
+impl Heuristic {
+
+    // The weight for a sample made with pdf when the other way of sampling would have given other_pdf
+    pub fn weight(self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            Heuristic::Balance => (pdf, other_pdf),
+            Heuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 { a / (a + b) } else { 0.0 }
+    }
+}
+
+
+// Light arriving straight from the scene's lights at a hit, through one shadow ray towards a
+// random point on them, weighted against the chance of the material having picked that direction
+fn direct_light(r: &Ray, hr: &HitRecord, scene: &Scene, heuristic: Heuristic) -> Colour {
+    let direction = match scene.lights.random_direction(hr.p) {
+        Some(direction) => direction.unit_vector(),
+        None => return Colour::new(),
+    };
+    let light_pdf = scene.lights.pdf_value(hr.p, direction, r.time);
+    let shadow = Ray::with_time(hr.p, direction, r.time);
+    let light_hit = match scene.lights.hit(&shadow, 0.001, f64::MAX) {
+        Some(light_hit) if light_pdf > 0.0 => light_hit,
+        _ => return Colour::new(),
+    };
+    // anything in the way leaves the point in shadow
+    if scene.world.hit(&shadow, 0.001, light_hit.t - 0.001).is_some() {
+        return Colour::new();
+    }
+
+    let f = hr.mat_ptr.eval(r, hr, direction);
+    let weight = heuristic.weight(light_pdf, hr.mat_ptr.pdf(r, hr, direction));
+    f * light_hit.mat_ptr.emitted(&shadow, &light_hit) * weight / light_pdf
+}
+
+
+// Light from the background in a direction picked by the background itself, for environment
+// maps, weighted against the chance of the material having picked that direction
+fn environment_light(r: &Ray, hr: &HitRecord, scene: &Scene, heuristic: Heuristic) -> Colour {
+    let (direction, pdf) = match scene.background.sample() {
+        Some((direction, pdf)) if pdf > 0.0 => (direction, pdf),
+        _ => return Colour::new(),
+    };
+    let shadow = Ray::with_time(hr.p, direction, r.time);
+    if scene.world.hit(&shadow, 0.001, f64::MAX).is_some() {
+        return Colour::new();
+    }
+
+    let f = hr.mat_ptr.eval(r, hr, direction);
+    let weight = heuristic.weight(pdf, hr.mat_ptr.pdf(r, hr, direction));
+    f * scene.background.colour(&shadow) * weight / pdf
+}
+
+
+// Light from every point, spot and sun light at a hit. These can only be found by sampling them,
+// so there is nothing to weigh them against
+fn delta_light(r: &Ray, hr: &HitRecord, scene: &Scene) -> Colour {
+    let mut colour = Colour::new();
+    for light in scene.delta_lights.iter() {
+        let sample = match light.sample(hr.p) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let shadow = Ray::with_time(hr.p, sample.direction, r.time);
+        if scene.world.hit(&shadow, 0.001, sample.distance - 0.001).is_none() {
+            colour = colour + hr.mat_ptr.eval(r, hr, sample.direction) * sample.radiance;
+        }
+    }
+    colour
+}
+
+
+// Follows a path from the camera for up to max_depth bounces. At every bounce that is not
+// specular the lights are sampled directly, and a light the path then runs into is weighted
+// against that, so that between them each light is counted once
+pub fn ray_colour(r: Ray, scene: &Scene, max_depth: u8, heuristic: Heuristic) -> Colour {
+    let mut colour = Colour::new();
+    // how much of the light found from here on reaches the camera
+    let mut throughput = Colour { x: 1.0, y: 1.0, z: 1.0 };
+    let mut ray = r;
+    // the pdf the material picked ray's direction with, None for the camera ray and specular bounces,
+    // which light sampling could never have found
+    let mut bsdf_pdf: Option<f64> = None;
+
+    for _ in 0..max_depth {
+        let hr = match scene.world.hit(&ray, 0.001, f64::MAX) {
+            Some(hr) => hr,
+            None => {
+                // the background may have been sampled at the last bounce as well
+                let weight = match bsdf_pdf {
+                    Some(pdf) => heuristic.weight(pdf, scene.background.pdf(ray.dir)),
+                    None => 1.0,
+                };
+                return colour + throughput * scene.background.colour(&ray) * weight;
+            }
+        };
+
+        let emitted = hr.mat_ptr.emitted(&ray, &hr);
+        let weight = match bsdf_pdf {
+            Some(pdf) if hr.sampled_light => {
+                heuristic.weight(pdf, scene.lights.pdf_value(ray.orig, ray.dir, ray.time))
+            }
+            _ => 1.0,
+        };
+        colour = colour + throughput * emitted * weight;
+
+        let sample = match hr.mat_ptr.sample(&ray, &hr) {
+            Some(sample) => sample,
+            None => break,
+        };
+        if !sample.specular {
+            if !scene.lights.is_empty() {
+                colour = colour + throughput * direct_light(&ray, &hr, scene, heuristic);
+            }
+            colour = colour + throughput * environment_light(&ray, &hr, scene, heuristic);
+            colour = colour + throughput * delta_light(&ray, &hr, scene);
+        }
+
+        throughput = throughput * sample.weight();
+        bsdf_pdf = if sample.specular { None } else { Some(sample.pdf) };
+        ray = Ray::with_time(hr.p, sample.direction, ray.time);
+    }
+    colour
+}
+
+
+
+// TEST
+#[test]
+fn test_heuristic_weights() {
+    for heuristic in [Heuristic::Balance, Heuristic::Power].iter() {
+        // the two weights for the same direction always add up to 1
+        let (a, b) = (0.3, 1.7);
+        assert!((heuristic.weight(a, b) + heuristic.weight(b, a) - 1.0).abs() < 1e-12);
+        // a way of sampling that could not have found the direction leaves it all to the other
+        assert_eq!(heuristic.weight(a, 0.0), 1.0);
+        assert_eq!(heuristic.weight(0.0, 0.0), 0.0);
+    }
+    assert_eq!(Heuristic::Balance.weight(1.0, 3.0), 0.25);
+    assert_eq!(Heuristic::Power.weight(1.0, 3.0), 0.1);
+}
+
+#[test]
+fn test_estimators_agree() {
+    use std::sync::Arc;
+    use crate::planar::Planar;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::background::Background;
+    use crate::camera::Camera;
+
+    // a grey floor lit by a square lamp above it, seen from straight above the floor
+    let floor = Arc::new(Planar::xz_rect(-100.0, 100.0, -100.0, 100.0, 0.0, Arc::new(Lambertian::new(Colour { x: 0.5, y: 0.5, z: 0.5 }))));
+    let lamp = Arc::new(Planar::xz_rect(-0.5, 0.5, -0.5, 0.5, 1.0, Arc::new(DiffuseLight { emit: Colour { x: 4.0, y: 4.0, z: 4.0 } })));
+    let scene = |sample_lights: bool| {
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        world.add(floor.clone());
+        if sample_lights {
+            world.add(Arc::new(SampledLight { object: lamp.clone() }));
+            lights.add(lamp.clone());
+        } else {
+            world.add(lamp.clone());
+        }
+        let up = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+        Scene {
+            world: Arc::new(world),
+            lights: Arc::new(lights),
+            delta_lights: Vec::new(),
+            camera: Camera::new(up, Point3::new(), Vec3 { x: 0.0, y: 0.0, z: 1.0 }, 90.0, 1.0, 0.0, 1.0),
+            background: Background::Solid(Colour::new()),
+        }
+    };
+    let ray = Ray::new(Point3 { x: 0.0, y: 0.5, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let n = 100000;
+    let mean = |scene: &Scene, heuristic: Heuristic| {
+        (0..n).map(|_| ray_colour(ray.clone(), scene, 4, heuristic).x).sum::<f64>() / n as f64
+    };
+
+    // the floor only ever sees the lamp by bouncing towards it, so every way of finding it must agree
+    let bsdf_only = mean(&scene(false), Heuristic::Balance);
+    let sampled = scene(true);
+    for heuristic in [Heuristic::Balance, Heuristic::Power].iter() {
+        let estimate = mean(&sampled, *heuristic);
+        assert!((estimate - bsdf_only).abs() < 0.03 * bsdf_only, "{:?} {} {}", heuristic, estimate, bsdf_only);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::vec3::*;
+
+
+// What a light sends towards a point
+pub struct LightSample {
+    // unit length, from the point towards the light
+    pub direction: Vec3,
+    // how far along direction the light is, for the shadow ray
+    pub distance: f64,
+    // the light arriving at the point, already fallen off with distance
+    pub radiance: Colour,
+}
+
+// Lights with no size at all. Rays can never hit them, so they are only ever found by
+// sampling them from the point being shaded
+pub trait Light: Send + Sync {
+    // None when none of the light reaches p
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+}
+
+
+// Shines equally in every direction from one place, falling off with the square of the distance
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Colour,
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_sqred = to_light.len_sqred();
+        if distance_sqred == 0.0 {
+            return None;
+        }
+        let distance = distance_sqred.sqrt();
+        Some(LightSample { direction: to_light / distance, distance, radiance: self.intensity / distance_sqred })
+    }
+}
+
+
+// A point light that only shines within angle degrees of direction, fading out over the last
+// falloff degrees of that so the edge of the pool of light is soft
+pub struct SpotLight {
+    pub position: Point3,
+    pub direction: Vec3,
+    pub intensity: Colour,
+    pub angle: f64,
+    pub falloff: f64,
+}
+
+impl SpotLight {
+
+    // 1 inside the cone, easing to 0 across the falloff at its edge
+    fn cone(&self, cosine: f64) -> f64 {
+        let cos_outer = self.angle.to_radians().cos();
+        let cos_inner = (self.angle - self.falloff).to_radians().cos();
+        if cosine >= cos_inner {
+            1.0
+        } else if cosine <= cos_outer {
+            0.0
+        } else {
+            let x = (cosine - cos_outer) / (cos_inner - cos_outer);
+            x * x * (3.0 - 2.0 * x)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let point = PointLight { position: self.position, intensity: self.intensity }.sample(p)?;
+        let strength = self.cone(-point.direction.dot(self.direction.unit_vector()));
+        if strength <= 0.0 {
+            return None;
+        }
+        Some(LightSample { radiance: strength * point.radiance, ..point })
+    }
+}
+
+
+// Light from so far away, like the sun, that it arrives everywhere from the same direction
+// and as strongly. direction is the way the light travels
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub intensity: Colour,
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _ : Point3) -> Option<LightSample> {
+        Some(LightSample { direction: -self.direction.unit_vector(), distance: f64::INFINITY, radiance: self.intensity })
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_spot_light_cone() {
+    let spot = SpotLight {
+        position: Point3 { x: 0.0, y: 2.0, z: 0.0 },
+        direction: Vec3 { x: 0.0, y: -1.0, z: 0.0 },
+        intensity: Colour { x: 4.0, y: 4.0, z: 4.0 },
+        angle: 45.0,
+        falloff: 10.0,
+    };
+    // straight below, at the full inverse square strength
+    let below = spot.sample(Point3::new()).unwrap();
+    assert_eq!(below.direction, Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    assert_eq!(below.distance, 2.0);
+    assert!((below.radiance - Colour { x: 1.0, y: 1.0, z: 1.0 }).len() < 1e-12);
+    // 40 degrees out is on the soft edge, 50 is out of the cone
+    let at = |degrees: f64| Point3 { x: 2.0 * degrees.to_radians().tan(), y: 0.0, z: 0.0 };
+    let edge = spot.sample(at(40.0)).unwrap();
+    assert!(edge.radiance.x > 0.0 && edge.radiance.x < 4.0 / (2.0 / 40f64.to_radians().cos()).powi(2));
+    assert!(spot.sample(at(50.0)).is_none());
+}
@@ -1,26 +1,98 @@
 
-use rand::prelude::*;
+use crate::random::*;
 use crate::hittable::*;
 use crate::vec3::*;
 use crate::ray::*;
+use crate::texture::*;
+use std::sync::Arc;
+use std::f64::consts::PI;
 
 
+// A direction picked by a material for the next bounce, towards where the light is gathered from
+pub struct BsdfSample {
+    // unit length
+    pub direction: Vec3,
+    // the BSDF times the cosine towards direction, or for a specular lobe all of the light sent that way
+    pub value: Colour,
+    // the density per unit of solid angle of picking direction, 1 for specular lobes
+    pub pdf: f64,
+    // perfect mirrors and glass, whose one direction nothing but the material itself could find
+    pub specular: bool,
+}
+
+impl BsdfSample {
+
+    // What the light found along direction is multiplied by
+    pub fn weight(&self) -> Colour {
+        self.value / self.pdf
+    }
+}
+
+
+// For every direction but specular ones a material can say how much light it passes on (eval)
+// and how likely sample is to pick it (pdf), which is what lets lights be sampled instead
 pub trait Material : Send + Sync  {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord ) -> Option<(Ray,Colour)> ;
+    // None when the surface absorbs the ray
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<BsdfSample>;
+
+    // The BSDF times the cosine for light arriving from the unit direction and leaving back along
+    // the incoming ray. Specular lobes are left out, as no other direction meets them exactly
+    fn eval(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> Colour {
+        Colour::new()
+    }
+
+    fn pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    // Light given off by the surface itself, nothing for most materials
+    fn emitted(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Colour {
+        Colour::new()
+    }
+}
+
+
+// The texture colour at the hit, tinted by the vertex colour if there is one
+fn albedo_at(texture: &Arc<dyn Texture>, hit_record: &HitRecord) -> Colour {
+    let albedo = texture.value(hit_record.u, hit_record.v, hit_record.p);
+    match hit_record.colour {
+        Some(colour) => albedo * colour,
+        None => albedo,
+    }
 }
 
 
 // Diffuse
 pub struct Lambertian {
-    pub albedo: Colour
+    pub albedo: Arc<dyn Texture>
 }
 
+impl Lambertian {
+    pub fn new(albedo: Colour) -> Lambertian {
+        Lambertian { albedo: Arc::new(SolidColour::new(albedo)) }
+    }
+}
 
+
+// Directions are picked in proportion to the cosine, which cancels it out of the weight
 impl Material for Lambertian {
-    fn scatter(&self, _ : &Ray, hit_record: &HitRecord) -> Option<(Ray,Colour)> {
-        let scatter_direction = hit_record.normal + Vec3::random_in_unit_vector();
-        let ray =  Ray::new(hit_record.p, scatter_direction);
-        return Some((ray,self.albedo))
+    fn sample(&self, ray_in : &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
+        let direction = Onb::build_from_w(hit_record.normal).local(Vec3::random_cosine_direction());
+        let pdf = self.pdf(ray_in, hit_record, direction);
+        // only exactly along the surface, which leaves nothing to pass on
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { direction, value: self.eval(ray_in, hit_record, direction), pdf, specular: false })
+    }
+
+    fn eval(&self, _ : &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        let cosine = hit_record.normal.dot(direction).max(0.0);
+        albedo_at(&self.albedo, hit_record) * cosine / PI
+    }
+
+    fn pdf(&self, _ : &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        hit_record.normal.dot(direction).max(0.0) / PI
     }
 }
 
@@ -30,15 +102,75 @@ impl Material for Lambertian {
 //     return  v - 2.0*v.dot(n)*n;
 // }
 pub struct Metal {
-    pub albedo: Colour,
+    pub albedo: Arc<dyn Texture>,
     pub fuzz  : f64,   
 }
 
+impl Metal {
+    pub fn new(albedo: Colour, fuzz: f64) -> Metal {
+        Metal { albedo: Arc::new(SolidColour::new(albedo)), fuzz }
+    }
+}
+
+// Without fuzz a perfect mirror. Fuzzy metal spreads its reflection over a lobe around the
+// mirror direction, falling off as cos^exponent of the angle away from it, and picks directions
+// by that same lobe. Directions that end up below the surface are absorbed.
+impl Metal {
+
+    // Matches the spread of the old fuzz, a random offset of up to fuzz added to the mirror direction
+    fn exponent(&self) -> f64 {
+        (5.0 / (self.fuzz * self.fuzz) - 2.0).max(0.0)
+    }
+
+    fn mirror(ray_in: &Ray, hit_record: &HitRecord) -> Vec3 {
+        Vec3::reflect(Vec3::unit_vector(ray_in.dir), hit_record.normal)
+    }
+
+    fn lobe(&self, ray_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let cosine = Metal::mirror(ray_in, hit_record).dot(direction);
+        if cosine <= 0.0 || hit_record.normal.dot(direction) <= 0.0 {
+            return 0.0;
+        }
+        let n = self.exponent();
+        (n + 1.0) / (2.0 * PI) * cosine.powf(n)
+    }
+}
+
 impl Material for Metal {
-    fn scatter(&self, ray_in : &Ray, hit_record: &HitRecord) -> Option<(Ray,Colour)> {
-        let reflected  = Vec3::reflect(Vec3::unit_vector(ray_in.dir),hit_record.normal);
-        let ray =  Ray::new(hit_record.p, reflected+ self.fuzz*Vec3::random_in_unit_sphere());
-        return Some((ray,self.albedo))
+    fn sample(&self, ray_in : &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
+        let reflected = Metal::mirror(ray_in, hit_record);
+        let attenuation = albedo_at(&self.albedo, hit_record);
+        if self.fuzz <= 0.0 {
+            return Some(BsdfSample { direction: reflected, value: attenuation, pdf: 1.0, specular: true });
+        }
+
+        // cos^n distributed about the mirror direction
+        let n = self.exponent();
+        let cosine = random_double().powf(1.0 / (n + 1.0));
+        let sine = (1.0 - cosine * cosine).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_double();
+        let direction = Onb::build_from_w(reflected).local(Vec3 { x: sine * phi.cos(), y: sine * phi.sin(), z: cosine });
+
+        let pdf = self.pdf(ray_in, hit_record, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { direction, value: self.eval(ray_in, hit_record, direction), pdf, specular: false })
+    }
+
+    // The lobe times the albedo, so a sample's weight is the albedo just as for the mirror
+    fn eval(&self, ray_in : &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        if self.fuzz <= 0.0 {
+            return Colour::new();
+        }
+        albedo_at(&self.albedo, hit_record) * self.lobe(ray_in, hit_record, direction)
+    }
+
+    fn pdf(&self, ray_in : &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        if self.fuzz <= 0.0 {
+            return 0.0;
+        }
+        self.lobe(ray_in, hit_record, direction)
     }
 }
 
@@ -55,13 +187,12 @@ fn reflectance(cosine:f64, ref_idx:f64) -> f64 {
     let mut r0 = (1.0-ref_idx) / (1.0+ref_idx);
     r0 = r0*r0;
 
-    return r0 + (1.0-r0)*f64::powi(1.0-cosine,5);//((1-cosine),5);
+    r0 + (1.0-r0)*f64::powi(1.0-cosine,5)//((1-cosine),5)
 }
 
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in : &Ray, hit_record: &HitRecord) -> Option<(Ray,Colour)> {
-        let mut rng = rand::thread_rng();
+    fn sample(&self, ray_in : &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
         let attenuation = Vec3{ x:1.0, y:1.0, z:1.0};  
 
         // Is this coming into or out of the di-electric ?
@@ -80,20 +211,127 @@ impl Material for Dielectric {
         let sin_theta = f64::sqrt(1.0 - cos_theta* cos_theta);
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction;
 
-        if cannot_refract || reflectance(cos_theta,refraction_ratio) > rng.gen::<f64>()   {
-            direction = Vec3::reflect(unit_direction, hit_record.normal)
+        let direction = if cannot_refract || reflectance(cos_theta,refraction_ratio) > random_double()   {
+            Vec3::reflect(unit_direction, hit_record.normal)
         } else {
-            direction = Vec3::refract(unit_direction,hit_record.normal,refraction_ratio);
-        }
+            Vec3::refract(unit_direction,hit_record.normal,refraction_ratio)
+        };
 
-        let scattered_ray = Ray::new(hit_record.p, direction);
         // I.e. no attention todo: add code to make di-electric a coloured sphere i.e. rose tinted
 
-        return Some((scattered_ray,attenuation));
+        Some(BsdfSample { direction, value: attenuation, pdf: 1.0, specular: true })
 
     }
 }
 
 
+
+//  Emissive
+pub struct DiffuseLight {
+    pub emit: Colour
+}
+
+impl Material for DiffuseLight {
+    // Lights absorb everything that hits them
+    fn sample(&self, _ : &Ray, _ : &HitRecord) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _ : &Ray, _ : &HitRecord) -> Colour {
+        self.emit
+    }
+}
+
+
+
+// Phase function for volumes, scatters equally in every direction
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Colour) -> Isotropic {
+        Isotropic { albedo: Arc::new(SolidColour::new(albedo)) }
+    }
+}
+
+impl Material for Isotropic {
+    fn sample(&self, ray_in : &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
+        let direction = Vec3::random_in_unit_vector();
+        Some(BsdfSample {
+            direction,
+            value: self.eval(ray_in, hit_record, direction),
+            pdf: self.pdf(ray_in, hit_record, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, _ : &Ray, hit_record: &HitRecord, _ : Vec3) -> Colour {
+        albedo_at(&self.albedo, hit_record) / (4.0 * PI)
+    }
+
+    fn pdf(&self, _ : &Ray, _ : &HitRecord, _ : Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_lambertian_sampling() {
+    let albedo = Colour { x: 0.2, y: 0.4, z: 0.6 };
+    let mat = Arc::new(Lambertian::new(albedo));
+    let normal = Vec3 { x: 0.0, y: 0.6, z: 0.8 };
+    let hit = HitRecord { p: Point3::new(), normal, mat_ptr: mat.clone(), t: 1.0, u: 0.0, v: 0.0, front_face: true, colour: None, sampled_light: false };
+    let ray_in = Ray::new(Point3 { x: 0.0, y: 1.0, z: 1.0 }, Vec3 { x: 0.0, y: -1.0, z: -1.0 });
+
+    let n = 10000;
+    let mut mean_cosine = 0.0;
+    for _ in 0..n {
+        let sample = mat.sample(&ray_in, &hit).unwrap();
+        assert!(!sample.specular);
+        assert!((sample.direction.len() - 1.0).abs() < 1e-9);
+        assert!((sample.pdf - mat.pdf(&ray_in, &hit, sample.direction)).abs() < 1e-12);
+        // the cosine in the BSDF and the pdf cancel, leaving just the albedo
+        assert!((sample.weight() - albedo).len() < 1e-9);
+        mean_cosine += normal.dot(sample.direction) / n as f64;
+    }
+    // cosine weighted directions average a cosine of 2/3
+    assert!((mean_cosine - 2.0 / 3.0).abs() < 0.01);
+}
+
+#[test]
+fn test_metal_sampling() {
+    let albedo = Colour { x: 0.9, y: 0.6, z: 0.3 };
+    let normal = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+    let ray_in = Ray::new(Point3 { x: -1.0, y: 1.0, z: 0.0 }, Vec3 { x: 1.0, y: -1.0, z: 0.0 });
+    let hit_with = |mat: Arc<dyn Material>| HitRecord { p: Point3::new(), normal, mat_ptr: mat, t: 1.0, u: 0.0, v: 0.0, front_face: true, colour: None, sampled_light: false };
+
+    // fuzzy metal is a lobe that can be evaluated, and its samples say the same as eval and pdf
+    let fuzzy = Arc::new(Metal::new(albedo, 0.3));
+    let hit = hit_with(fuzzy.clone());
+    let mut sampled = 0;
+    for _ in 0..1000 {
+        let sample = match fuzzy.sample(&ray_in, &hit) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        sampled += 1;
+        assert!(!sample.specular);
+        assert!((sample.pdf - fuzzy.pdf(&ray_in, &hit, sample.direction)).abs() < 1e-9 * sample.pdf);
+        assert!((sample.value - fuzzy.eval(&ray_in, &hit, sample.direction)).len() < 1e-9 * sample.value.len());
+        assert!((sample.weight() - albedo).len() < 1e-9);
+    }
+    assert!(sampled > 900, "{}", sampled);
+
+    // without fuzz it is a mirror, which only its one direction can find
+    let mirror = Arc::new(Metal::new(albedo, 0.0));
+    let hit = hit_with(mirror.clone());
+    let sample = mirror.sample(&ray_in, &hit).unwrap();
+    assert!(sample.specular);
+    assert!((sample.direction - Vec3 { x: 1.0, y: 1.0, z: 0.0 }.unit_vector()).len() < 1e-12);
+    assert_eq!(mirror.pdf(&ray_in, &hit, sample.direction), 0.0);
+    assert_eq!(mirror.eval(&ray_in, &hit, sample.direction), Colour::new());
+}
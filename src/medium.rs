@@ -0,0 +1,107 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use crate::random::*;
+use std::sync::Arc;
+
+
+// Fog or smoke of the same density everywhere inside a closed boundary.
+// A ray going through it scatters after an exponentially distributed distance, or passes
+// straight through if that is further than the ray spends inside.
+pub struct ConstantMedium {
+    pub boundary: Arc<dyn Hittable>,
+    pub phase_function: Arc<dyn Material>,
+    neg_inv_density: f64,
+}
+
+impl ConstantMedium {
+
+    // density is the chance of scattering per unit of distance travelled
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, phase_function: Arc<dyn Material>) -> ConstantMedium {
+        ConstantMedium { boundary, phase_function, neg_inv_density: -1.0 / density }
+    }
+
+    // The stretches of the ray inside the boundary, clipped to [tmin, tmax].
+    // Boundaries without intervals are taken to be convex, so the first two hits are the way in and out
+    fn inside(&self, ray: &Ray, tmin: f64, tmax: f64) -> Vec<(f64, f64)> {
+        let stretches = match self.boundary.intervals(ray) {
+            Some(intervals) => intervals.into_iter().map(|(enter, exit)| (enter.t, exit.t)).collect(),
+            None => {
+                let enter = match self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY) {
+                    Some(hit) => hit.t,
+                    None => return Vec::new(),
+                };
+                match self.boundary.hit(ray, enter + 0.0001, f64::INFINITY) {
+                    Some(exit) => vec![(enter, exit.t)],
+                    None => return Vec::new(),
+                }
+            }
+        };
+        stretches
+            .into_iter()
+            .map(|(enter, exit): (f64, f64)| (enter.max(tmin), exit.min(tmax)))
+            .filter(|(enter, exit)| enter < exit)
+            .collect()
+    }
+}
+
+impl Hittable for ConstantMedium {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let ray_length = ray.dir.len();
+        // the distance is only drawn once, free flight is memoryless so the gaps between stretches do not matter
+        let mut hit_distance = self.neg_inv_density * random_double().ln();
+
+        for (enter, exit) in self.inside(ray, tmin, tmax) {
+            let distance_inside = (exit - enter) * ray_length;
+            if hit_distance < distance_inside {
+                let t = enter + hit_distance / ray_length;
+                return Some(HitRecord {
+                    p: ray.clone().at(t),
+                    // neither matters to an isotropic phase function
+                    normal: Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+                    front_face: true,
+                    mat_ptr: self.phase_function.clone(),
+                    t,
+                    u: 0.0,
+                    v: 0.0,
+                    colour: None,
+                    sampled_light: false,
+                });
+            }
+            hit_distance -= distance_inside;
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_constant_medium() {
+    use crate::sphere::Sphere;
+
+    let mat = Arc::new(Lambertian::new(Colour::new()));
+    let phase = Arc::new(Isotropic::new(Colour { x: 1.0, y: 1.0, z: 1.0 }));
+    let boundary = Arc::new(Sphere { center: Point3::new(), radius: 1.0, mat_ptr: mat });
+    let ray = Ray::new(Point3 { x: -5.0, y: 0.0, z: 0.0 }, Vec3 { x: 2.0, y: 0.0, z: 0.0 });
+
+    // thick enough that it always scatters, and always inside the sphere
+    let dense = ConstantMedium::new(boundary.clone(), 1e9, phase.clone());
+    for _ in 0..100 {
+        let hit = dense.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.t >= 2.0 && hit.t < 2.0 + 1e-6);
+    }
+
+    // a ray passing through 2 units of density 0.5 gets through e^-1 of the time
+    let thin = ConstantMedium::new(boundary, 0.5, phase);
+    let through = (0..20000).filter(|_| thin.hit(&ray, 0.001, f64::INFINITY).is_none()).count();
+    assert!((through as f64 / 20000.0 - (-1.0f64).exp()).abs() < 0.02);
+}
@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::triangle::*;
+
+
+// Wavefront OBJ models, with their materials from MTL files.
+// Polygons are split into triangle fans, so they should be convex.
+// Each group (`g` or `o`) becomes its own mesh, as does every change of material inside one.
+
+
+// The faces of one group that share a material
+pub struct ObjGroup {
+    pub name: String,
+    pub mesh: TriangleMesh,
+}
+
+// default_material is used for faces that come before any `usemtl`
+pub fn load_obj(path: &Path, default_material: Arc<dyn Material>) -> Result<Vec<ObjGroup>, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    parse_obj(&source, base_dir, default_material).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Every group in its own BVH, ready to add to a world
+pub fn into_hittable_list(groups: Vec<ObjGroup>) -> HittableList {
+    let mut list = HittableList::new();
+    for group in groups {
+        list.add(Arc::new(group.mesh.into_bvh()));
+    }
+    list
+}
+
+
+// Collects the faces of the current group, with its own compacted copies of
+// the vertex data so groups do not each hold the whole file
+struct GroupBuilder {
+    name: String,
+    material: Arc<dyn Material>,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    faces: Vec<MeshFace>,
+    position_map: HashMap<usize, usize>,
+    normal_map: HashMap<usize, usize>,
+    uv_map: HashMap<usize, usize>,
+}
+
+impl GroupBuilder {
+
+    fn new(name: String, material: Arc<dyn Material>) -> GroupBuilder {
+        GroupBuilder {
+            name,
+            material,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            faces: Vec::new(),
+            position_map: HashMap::new(),
+            normal_map: HashMap::new(),
+            uv_map: HashMap::new(),
+        }
+    }
+
+    fn finish(self) -> Result<Option<ObjGroup>, String> {
+        if self.faces.is_empty() {
+            return Ok(None);
+        }
+        let mesh = TriangleMesh::new(self.positions, self.normals, self.uvs, self.faces, self.material)?;
+        Ok(Some(ObjGroup { name: self.name, mesh }))
+    }
+}
+
+fn local_index<T: Copy>(global: usize, map: &mut HashMap<usize, usize>, all: &[T], local: &mut Vec<T>) -> usize {
+    *map.entry(global).or_insert_with(|| {
+        local.push(all[global]);
+        local.len() - 1
+    })
+}
+
+// OBJ indices start at 1, negative ones count back from the latest element
+fn resolve_index(token: &str, len: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = token.parse().map_err(|_| format!("bad {} index `{}`", what, token))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        len as i64 + index
+    } else {
+        return Err(format!("{} index cannot be 0", what));
+    };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("{} index {} out of range, there are {}", what, index, len));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(args: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if args.len() < min || args.len() > max {
+        return Err(format!("expected {} to {} numbers, got {}", min, max, args.len()));
+    }
+    args.iter()
+        .map(|arg| arg.parse::<f64>().map_err(|_| format!("bad number `{}`", arg)))
+        .collect()
+}
+
+// Joins lines ending in a backslash and drops comments, keeping the number of the first line
+fn logical_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, raw) in source.lines().enumerate() {
+        let text = match raw.find('#') {
+            Some(hash) => &raw[..hash],
+            None => raw,
+        };
+        let (start, mut line) = pending.take().unwrap_or((i + 1, String::new()));
+        match text.trim_end().strip_suffix('\\') {
+            Some(continued) => {
+                line.push_str(continued);
+                line.push(' ');
+                pending = Some((start, line));
+            }
+            None => {
+                line.push_str(text);
+                lines.push((start, line));
+            }
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+// base_dir is where `mtllib` files are looked up from
+pub fn parse_obj(source: &str, base_dir: &Path, default_material: Arc<dyn Material>) -> Result<Vec<ObjGroup>, String> {
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut materials: BTreeMap<String, Arc<dyn Material>> = BTreeMap::new();
+
+    let mut groups = Vec::new();
+    let mut current = GroupBuilder::new("default".to_string(), default_material);
+
+    for (line_no, line) in logical_lines(source) {
+        let at_line = |err: String| format!("line {}: {}", line_no, err);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                // an optional w is ignored
+                let v = parse_floats(&args, 3, 4).map_err(at_line)?;
+                positions.push(Point3 { x: v[0], y: v[1], z: v[2] });
+            }
+            "vn" => {
+                let n = parse_floats(&args, 3, 3).map_err(at_line)?;
+                normals.push(Vec3 { x: n[0], y: n[1], z: n[2] });
+            }
+            "vt" => {
+                let t = parse_floats(&args, 1, 3).map_err(at_line)?;
+                uvs.push((t[0], t.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(at_line(format!("a face needs at least 3 vertices, got {}", args.len())));
+                }
+                // each corner is v, v/vt, v//vn or v/vt/vn
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    let mut parts = arg.split('/');
+                    let v = resolve_index(parts.next().unwrap_or(""), positions.len(), "vertex").map_err(at_line)?;
+                    let vt = match parts.next() {
+                        Some(token) if !token.is_empty() => Some(resolve_index(token, uvs.len(), "texture").map_err(at_line)?),
+                        _ => None,
+                    };
+                    let vn = match parts.next() {
+                        Some(token) if !token.is_empty() => Some(resolve_index(token, normals.len(), "normal").map_err(at_line)?),
+                        _ => None,
+                    };
+                    corners.push((
+                        local_index(v, &mut current.position_map, &positions, &mut current.positions),
+                        vt.map(|vt| local_index(vt, &mut current.uv_map, &uvs, &mut current.uvs)),
+                        vn.map(|vn| local_index(vn, &mut current.normal_map, &normals, &mut current.normals)),
+                    ));
+                }
+
+                // fan around the first corner
+                for i in 1..corners.len() - 1 {
+                    let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+                    let all = |index: [Option<usize>; 3]| match index {
+                        [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+                        _ => None,
+                    };
+                    current.faces.push(MeshFace {
+                        vertices: [a.0, b.0, c.0],
+                        uvs: all([a.1, b.1, c.1]),
+                        normals: all([a.2, b.2, c.2]),
+                    });
+                }
+            }
+            "g" | "o" => {
+                let name = if args.is_empty() { "default".to_string() } else { args.join(" ") };
+                let material = current.material.clone();
+                let finished = std::mem::replace(&mut current, GroupBuilder::new(name, material));
+                groups.extend(finished.finish().map_err(at_line)?);
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                let material = match materials.get(&name) {
+                    Some(material) => material.clone(),
+                    None => return Err(at_line(format!("unknown material `{}`", name))),
+                };
+                let group_name = current.name.clone();
+                let finished = std::mem::replace(&mut current, GroupBuilder::new(group_name, material));
+                groups.extend(finished.finish().map_err(at_line)?);
+            }
+            "mtllib" => {
+                for file in args.iter() {
+                    let path = base_dir.join(file);
+                    let source = fs::read_to_string(&path)
+                        .map_err(|err| at_line(format!("could not read {}: {}", path.display(), err)))?;
+                    let descs = parse_mtl(&source).map_err(|err| format!("{}: {}", path.display(), err))?;
+                    for (name, desc) in descs {
+                        let material = desc.to_material(base_dir).map_err(|err| format!("{}: {}", path.display(), err))?;
+                        materials.insert(name, material);
+                    }
+                }
+            }
+            // smoothing groups, lines, points and curves are ignored
+            _ => {}
+        }
+    }
+    groups.extend(current.finish()?);
+
+    if groups.is_empty() {
+        return Err("no faces".to_string());
+    }
+    Ok(groups)
+}
+
+
+// The parts of an MTL material we can map onto our own materials
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlDesc {
+    pub diffuse: Colour,
+    pub specular: Colour,
+    pub emission: Colour,
+    // Ns, 0 to 1000
+    pub shininess: f64,
+    // Ni
+    pub ior: f64,
+    // d, 1 is opaque
+    pub dissolve: f64,
+    pub illum: u32,
+    pub diffuse_map: Option<String>,
+}
+
+impl Default for MtlDesc {
+    fn default() -> MtlDesc {
+        MtlDesc {
+            diffuse: Colour { x: 0.8, y: 0.8, z: 0.8 },
+            specular: Colour::new(),
+            emission: Colour::new(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None,
+        }
+    }
+}
+
+impl MtlDesc {
+
+    // Glowing surfaces become lights, transparent ones glass, mirrors (illum 3 or specular
+    // without diffuse) metal and everything else is diffuse.
+    pub fn to_material(&self, base_dir: &Path) -> Result<Arc<dyn Material>, String> {
+        let is_black = |c: Colour| c.x <= 0.0 && c.y <= 0.0 && c.z <= 0.0;
+
+        if !is_black(self.emission) {
+            return Ok(Arc::new(DiffuseLight { emit: self.emission }));
+        }
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let ir = if self.ior > 0.0 { self.ior } else { 1.5 };
+            return Ok(Arc::new(Dielectric { ir }));
+        }
+        if self.illum == 3 || (is_black(self.diffuse) && !is_black(self.specular)) {
+            let fuzz = 1.0 - (self.shininess / 1000.0).clamp(0.0, 1.0).sqrt();
+            return Ok(Arc::new(Metal::new(self.specular, fuzz)));
+        }
+        match &self.diffuse_map {
+            Some(file) => {
+                let texture = ImageTexture::load(&base_dir.join(file))?;
+                Ok(Arc::new(Lambertian { albedo: Arc::new(texture) }))
+            }
+            None => Ok(Arc::new(Lambertian::new(self.diffuse))),
+        }
+    }
+}
+
+pub fn parse_mtl(source: &str) -> Result<BTreeMap<String, MtlDesc>, String> {
+    let mut materials = BTreeMap::new();
+    let mut current: Option<(String, MtlDesc)> = None;
+
+    for (line_no, line) in logical_lines(source) {
+        let at_line = |err: String| format!("line {}: {}", line_no, err);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.extend(current.take());
+            current = Some((args.join(" "), MtlDesc::default()));
+            continue;
+        }
+        let desc = match current.as_mut() {
+            Some((_, desc)) => desc,
+            None => return Err(at_line(format!("`{}` before any newmtl", keyword))),
+        };
+
+        let colour = |args: &[&str]| -> Result<Colour, String> {
+            // a single value is used for all three channels
+            let c = parse_floats(args, 1, 3).map_err(at_line)?;
+            match c.len() {
+                3 => Ok(Colour { x: c[0], y: c[1], z: c[2] }),
+                1 => Ok(Colour { x: c[0], y: c[0], z: c[0] }),
+                _ => Err(at_line("expected 1 or 3 numbers".to_string())),
+            }
+        };
+        let number = |args: &[&str]| parse_floats(args, 1, 1).map(|v| v[0]).map_err(at_line);
+
+        match keyword {
+            "Kd" => desc.diffuse = colour(&args)?,
+            "Ks" => desc.specular = colour(&args)?,
+            "Ke" => desc.emission = colour(&args)?,
+            "Ns" => desc.shininess = number(&args)?,
+            "Ni" => desc.ior = number(&args)?,
+            "d" => desc.dissolve = number(&args)?,
+            "Tr" => desc.dissolve = 1.0 - number(&args)?,
+            "illum" => desc.illum = number(&args)? as u32,
+            // options like -s come first, the file name is last
+            "map_Kd" => desc.diffuse_map = args.last().map(|file| file.to_string()),
+            _ => {}
+        }
+    }
+    materials.extend(current);
+    Ok(materials)
+}
+
+
+
+// TEST
+#[test]
+fn test_parse_obj() {
+    let source = "\
+# a quad and a triangle
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+g quad
+f 1/1/1 2/2/1 3/3/1 4/4/1
+o tri
+v 5 5 5
+f -1 1 \\
+  2
+";
+    let material = Arc::new(Lambertian::new(Colour::new()));
+    let groups = parse_obj(source, Path::new("."), material.clone()).unwrap();
+    assert_eq!(groups.len(), 2);
+
+    assert_eq!(groups[0].name, "quad");
+    let quad = &groups[0].mesh;
+    assert_eq!(quad.faces.len(), 2);
+    assert_eq!(quad.positions.len(), 4);
+    assert_eq!(quad.normals.len(), 1);
+    assert_eq!(quad.faces[1], MeshFace { vertices: [0, 2, 3], normals: Some([0, 0, 0]), uvs: Some([0, 2, 3]) });
+
+    assert_eq!(groups[1].name, "tri");
+    let tri = &groups[1].mesh;
+    assert_eq!(tri.positions, vec![Point3 { x: 5.0, y: 5.0, z: 5.0 }, Point3::new(), Point3 { x: 1.0, y: 0.0, z: 0.0 }]);
+    assert_eq!(tri.faces[0].normals, None);
+
+    let err = parse_obj("v 0 0 0\nf 1 2 3\n", Path::new("."), material.clone()).err().unwrap();
+    assert!(err.starts_with("line 2"), "{}", err);
+    let err = parse_obj("v 0 0 0\nusemtl nope\n", Path::new("."), material).err().unwrap();
+    assert!(err.contains("nope"), "{}", err);
+}
+
+#[test]
+fn test_parse_mtl() {
+    let source = "\
+newmtl red
+Kd 0.8 0.1 0.1
+newmtl glass
+illum 7
+Ni 1.33
+newmtl lamp
+Ke 5 5 5
+";
+    let materials = parse_mtl(source).unwrap();
+    assert_eq!(materials.len(), 3);
+    assert_eq!(materials["red"].diffuse, Colour { x: 0.8, y: 0.1, z: 0.1 });
+    assert_eq!(materials["glass"].ior, 1.33);
+    assert_eq!(materials["glass"].illum, 7);
+
+    let lamp = materials["lamp"].to_material(Path::new(".")).unwrap();
+    let hit = HitRecord {
+        p: Point3::new(),
+        normal: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        mat_ptr: lamp.clone(),
+        t: 1.0,
+        u: 0.0,
+        v: 0.0,
+        front_face: true,
+        colour: None,
+        sampled_light: false,
+    };
+    let ray = crate::ray::Ray::new(Point3::new(), Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    assert_eq!(lamp.emitted(&ray, &hit), Colour { x: 5.0, y: 5.0, z: 5.0 });
+
+    assert!(parse_mtl("Kd 1 1 1\n").is_err());
+}
@@ -0,0 +1,157 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::vec3::*;
+
+
+const POINT_COUNT: usize = 256;
+
+// Ken Perlin's gradient noise, with random unit vectors at the lattice points.
+// The tables come from their own seeded generator, so a texture looks the same
+// on every render no matter what --seed is.
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let ranvec = (0..POINT_COUNT)
+            .map(|_| {
+                loop {
+                    let v = Vec3 {
+                        x: rng.gen_range(-1.0, 1.0),
+                        y: rng.gen_range(-1.0, 1.0),
+                        z: rng.gen_range(-1.0, 1.0),
+                    };
+                    // rejecting the corners of the cube keeps the directions uniform
+                    let len_sqred = v.len_sqred();
+                    if len_sqred > 1e-6 && len_sqred <= 1.0 {
+                        return v.unit_vector();
+                    }
+                }
+            })
+            .collect();
+
+        Perlin {
+            ranvec,
+            perm_x: Perlin::generate_perm(&mut rng),
+            perm_y: Perlin::generate_perm(&mut rng),
+            perm_z: Perlin::generate_perm(&mut rng),
+        }
+    }
+
+    fn generate_perm(rng: &mut StdRng) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        p.shuffle(rng);
+        p
+    }
+
+    // Smooth noise in roughly [-1,1], zero at every lattice point
+    pub fn noise(&self, p: Point3) -> f64 {
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+
+        let mut c = [[[Vec3::new(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    *corner = self.ranvec[index];
+                }
+            }
+        }
+
+        perlin_interp(&c, u, v, w)
+    }
+
+    // Sum of the absolute value of noise at doubling frequencies and halving weights.
+    // Always positive, the creases give marble its veins.
+    pub fn turbulence(&self, p: Point3, octaves: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(temp_p).abs();
+            weight *= 0.5;
+            temp_p = temp_p * 2.0;
+        }
+        accum
+    }
+
+    // Fractional Brownian motion, signed noise summed over octaves.
+    // lacunarity scales the frequency and gain the weight from one octave to the next.
+    pub fn fbm(&self, p: Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(temp_p);
+            weight *= gain;
+            temp_p = temp_p * lacunarity;
+        }
+        accum
+    }
+}
+
+// Trilinear interpolation of the gradients, with a Hermite cubic to hide the lattice
+fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+    let mut accum = 0.0;
+
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, corner) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight_v = Vec3 { x: u - fi, y: v - fj, z: w - fk };
+                accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * corner.dot(weight_v);
+            }
+        }
+    }
+    accum
+}
+
+
+
+// TEST
+#[test]
+fn test_perlin_is_deterministic() {
+    let a = Perlin::new(7);
+    let b = Perlin::new(7);
+    let c = Perlin::new(8);
+    let p = Point3 { x: 1.3, y: -2.7, z: 0.45 };
+    assert_eq!(a.noise(p), b.noise(p));
+    assert_ne!(a.noise(p), c.noise(p));
+}
+
+#[test]
+fn test_perlin_range() {
+    let perlin = Perlin::new(1);
+    // zero on the lattice
+    assert_eq!(perlin.noise(Point3 { x: 3.0, y: -1.0, z: 2.0 }), 0.0);
+    for i in 0..1000 {
+        let p = Point3 { x: i as f64 * 0.137, y: i as f64 * -0.071, z: i as f64 * 0.029 };
+        let n = perlin.noise(p);
+        assert!((-1.0..=1.0).contains(&n), "{}", n);
+        assert!(perlin.turbulence(p, 7) >= 0.0);
+    }
+}
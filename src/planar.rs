@@ -0,0 +1,337 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::*;
+use crate::random::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+
+// Which part of the plane is solid, in terms of the (alpha, beta) coordinates along u and v
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanarShape {
+    // 0 <= alpha, beta <= 1
+    Parallelogram,
+    // alpha, beta >= 0 and alpha + beta <= 1
+    Triangle,
+    // alpha^2 + beta^2 <= 1, with q at the centre
+    Disk,
+}
+
+// A flat shape spanned by two edge vectors u and v from the corner (or centre) q.
+// The front is the side u x v points to.
+pub struct Planar {
+    pub q: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub shape: PlanarShape,
+    pub mat_ptr: Arc<dyn Material>,
+    normal: Vec3,
+    d: f64,
+    // n / (n . n), turns a point on the plane into its (alpha, beta)
+    w: Vec3,
+}
+
+impl Planar {
+
+    pub fn new(q: Point3, u: Vec3, v: Vec3, shape: PlanarShape, mat_ptr: Arc<dyn Material>) -> Planar {
+        let n = u.cross(v);
+        let normal = n.unit_vector();
+        Planar { q, u, v, shape, mat_ptr, normal, d: normal.dot(q), w: n / n.dot(n) }
+    }
+
+    pub fn quad(q: Point3, u: Vec3, v: Vec3, mat_ptr: Arc<dyn Material>) -> Planar {
+        Planar::new(q, u, v, PlanarShape::Parallelogram, mat_ptr)
+    }
+
+    // Counter clockwise corners face the viewer
+    pub fn triangle(a: Point3, b: Point3, c: Point3, mat_ptr: Arc<dyn Material>) -> Planar {
+        Planar::new(a, b - a, c - a, PlanarShape::Triangle, mat_ptr)
+    }
+
+    pub fn disk(center: Point3, normal: Vec3, radius: f64, mat_ptr: Arc<dyn Material>) -> Planar {
+        let normal = normal.unit_vector();
+        // any direction not parallel to the normal will do to start the in plane axes
+        let helper = if normal.x.abs() > 0.9 { Vec3 { x: 0.0, y: 1.0, z: 0.0 } } else { Vec3 { x: 1.0, y: 0.0, z: 0.0 } };
+        let u = normal.cross(helper).unit_vector();
+        let v = normal.cross(u);
+        Planar::new(center, radius * u, radius * v, PlanarShape::Disk, mat_ptr)
+    }
+
+    // Axis aligned rectangles at a fixed height k, facing +z, +y and +x
+    pub fn xy_rect(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mat_ptr: Arc<dyn Material>) -> Planar {
+        let q = Point3 { x: x0, y: y0, z: k };
+        Planar::quad(q, Vec3 { x: x1 - x0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: y1 - y0, z: 0.0 }, mat_ptr)
+    }
+
+    pub fn xz_rect(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mat_ptr: Arc<dyn Material>) -> Planar {
+        let q = Point3 { x: x0, y: k, z: z0 };
+        Planar::quad(q, Vec3 { x: 0.0, y: 0.0, z: z1 - z0 }, Vec3 { x: x1 - x0, y: 0.0, z: 0.0 }, mat_ptr)
+    }
+
+    pub fn yz_rect(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mat_ptr: Arc<dyn Material>) -> Planar {
+        let q = Point3 { x: k, y: y0, z: z0 };
+        Planar::quad(q, Vec3 { x: 0.0, y: y1 - y0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: z1 - z0 }, mat_ptr)
+    }
+
+    pub fn area(&self) -> f64 {
+        let parallelogram = self.u.cross(self.v).len();
+        match self.shape {
+            PlanarShape::Parallelogram => parallelogram,
+            PlanarShape::Triangle => 0.5 * parallelogram,
+            PlanarShape::Disk => PI * parallelogram,
+        }
+    }
+
+    // A point spread evenly over the shape
+    fn random_point(&self) -> Point3 {
+        let (alpha, beta) = match self.shape {
+            PlanarShape::Parallelogram => (random_double(), random_double()),
+            PlanarShape::Triangle => {
+                let (a, b) = (random_double(), random_double());
+                // the half of the square past the diagonal folds back onto the triangle
+                if a + b > 1.0 { (1.0 - a, 1.0 - b) } else { (a, b) }
+            }
+            PlanarShape::Disk => {
+                let p = Vec3::random_in_unit_disk();
+                (p.x, p.y)
+            }
+        };
+        self.q + alpha * self.u + beta * self.v
+    }
+
+    // (u, v) texture coordinates for a point in the plane, None if it is outside the shape
+    fn surface_uv(&self, alpha: f64, beta: f64) -> Option<(f64, f64)> {
+        match self.shape {
+            PlanarShape::Parallelogram if (0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta) => Some((alpha, beta)),
+            PlanarShape::Triangle if alpha >= 0.0 && beta >= 0.0 && alpha + beta <= 1.0 => Some((alpha, beta)),
+            PlanarShape::Disk if alpha * alpha + beta * beta <= 1.0 => Some((0.5 * (alpha + 1.0), 0.5 * (beta + 1.0))),
+            _ => None,
+        }
+    }
+}
+
+impl Hittable for Planar {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let denom = self.normal.dot(ray.dir);
+        // parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.d - self.normal.dot(ray.orig)) / denom;
+        if t <= tmin || t >= tmax {
+            return None;
+        }
+
+        let p = ray.clone().at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        let (u, v) = self.surface_uv(alpha, beta)?;
+
+        let front_face = denom < 0.0;
+        Some(HitRecord {
+            p,
+            normal: if front_face { self.normal } else { -self.normal },
+            mat_ptr: self.mat_ptr.clone(),
+            t,
+            u,
+            v,
+            front_face,
+            colour: None,
+            sampled_light: false,
+        })
+    }
+
+    // Points are spread evenly by area, which seen from origin is distance^2 / cos per unit of area
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let hit = match self.hit(&Ray::with_time(origin, direction, time), 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return 0.0,
+        };
+        let distance_sqred = hit.t * hit.t * direction.len_sqred();
+        let cosine = (direction.dot(self.normal) / direction.len()).abs();
+        if cosine < 1e-8 {
+            return 0.0;
+        }
+        distance_sqred / (cosine * self.area())
+    }
+
+    fn random_direction(&self, origin: Point3) -> Option<Vec3> {
+        Some(self.random_point() - origin)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = match self.shape {
+            PlanarShape::Disk => Aabb::surrounding_box(
+                Aabb::new(self.q - self.u - self.v, self.q + self.u + self.v),
+                Aabb::new(self.q - self.u + self.v, self.q + self.u - self.v),
+            ),
+            _ => Aabb::surrounding_box(
+                Aabb::new(self.q, self.q + self.u + self.v),
+                Aabb::new(self.q + self.u, self.q + self.v),
+            ),
+        };
+        Some(bbox.padded())
+    }
+}
+
+
+// An axis aligned box made of six quads, all facing out
+pub struct BoxShape {
+    pub minimum: Point3,
+    pub maximum: Point3,
+    pub mat_ptr: Arc<dyn Material>,
+    sides: HittableList,
+}
+
+impl BoxShape {
+
+    // Opposite corners, in any order
+    pub fn new(a: Point3, b: Point3, mat_ptr: Arc<dyn Material>) -> BoxShape {
+        let Aabb { minimum: min, maximum: max } = Aabb::new(a, b);
+        let dx = Vec3 { x: max.x - min.x, y: 0.0, z: 0.0 };
+        let dy = Vec3 { x: 0.0, y: max.y - min.y, z: 0.0 };
+        let dz = Vec3 { x: 0.0, y: 0.0, z: max.z - min.z };
+
+        let mut sides = HittableList::new();
+        let mut side = |q: Point3, u: Vec3, v: Vec3| sides.add(Arc::new(Planar::quad(q, u, v, mat_ptr.clone())));
+        side(Point3 { x: min.x, y: min.y, z: max.z }, dx, dy); // front
+        side(Point3 { x: max.x, y: min.y, z: max.z }, -dz, dy); // right
+        side(Point3 { x: max.x, y: min.y, z: min.z }, -dx, dy); // back
+        side(Point3 { x: min.x, y: min.y, z: min.z }, dz, dy); // left
+        side(Point3 { x: min.x, y: max.y, z: max.z }, dx, -dz); // top
+        side(min, dx, dz); // bottom
+
+        BoxShape { minimum: min, maximum: max, mat_ptr, sides }
+    }
+}
+
+impl Hittable for BoxShape {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        self.sides.hit(ray, tmin, tmax)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.minimum, self.maximum).padded())
+    }
+
+    // Slab test over the whole line, remembering which face each end is on
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let (mut t_near, mut t_far) = (f64::NEG_INFINITY, f64::INFINITY);
+        let (mut near_axis, mut far_axis) = (0, 0);
+        for axis in 0..3 {
+            let (o, d) = (ray.orig[axis], ray.dir[axis]);
+            if d == 0.0 {
+                if o < self.minimum[axis] || o > self.maximum[axis] {
+                    return Some(Vec::new());
+                }
+                continue;
+            }
+            let (t0, t1) = ((self.minimum[axis] - o) / d, (self.maximum[axis] - o) / d);
+            let (t0, t1) = if d < 0.0 { (t1, t0) } else { (t0, t1) };
+            if t0 > t_near { t_near = t0; near_axis = axis; }
+            if t1 < t_far { t_far = t1; far_axis = axis; }
+        }
+        if t_near >= t_far || t_near == f64::NEG_INFINITY {
+            return Some(Vec::new());
+        }
+
+        let size = self.maximum - self.minimum;
+        let side = |t: f64, axis: usize, entering: bool| {
+            let p = ray.clone().at(t);
+            let mut normal = [0.0; 3];
+            // the outward normal points back along the ray going in and along it coming out
+            normal[axis] = if (ray.dir[axis] > 0.0) == entering { -1.0 } else { 1.0 };
+            let outward_normal = Vec3 { x: normal[0], y: normal[1], z: normal[2] };
+            let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+            HitRecord {
+                p,
+                normal: if entering { outward_normal } else { -outward_normal },
+                mat_ptr: self.mat_ptr.clone(),
+                t,
+                u: (p[a] - self.minimum[a]) / size[a],
+                v: (p[b] - self.minimum[b]) / size[b],
+                front_face: entering,
+                colour: None,
+                sampled_light: false,
+            }
+        };
+        Some(vec![(side(t_near, near_axis, true), side(t_far, far_axis, false))])
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_planar_shapes() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let down = |x: f64, z: f64| Ray::new(Point3 { x, y: 1.0, z }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+
+    let rect = Planar::xz_rect(0.0, 2.0, 0.0, 1.0, 0.0, mat.clone());
+    let hit = rect.hit(&down(1.5, 0.5), 0.001, f64::INFINITY).unwrap();
+    assert_eq!(hit.normal, Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    assert!(hit.front_face);
+    assert!((hit.u - 0.5).abs() < 1e-12 && (hit.v - 0.75).abs() < 1e-12);
+    assert!(rect.hit(&down(2.5, 0.5), 0.001, f64::INFINITY).is_none());
+
+    let disk = Planar::disk(Point3::new(), Vec3 { x: 0.0, y: 1.0, z: 0.0 }, 1.0, mat.clone());
+    assert!(disk.hit(&down(0.6, 0.6), 0.001, f64::INFINITY).is_some());
+    assert!(disk.hit(&down(0.8, 0.8), 0.001, f64::INFINITY).is_none());
+
+    let triangle = Planar::triangle(
+        Point3::new(),
+        Point3 { x: 0.0, y: 0.0, z: 1.0 },
+        Point3 { x: 1.0, y: 0.0, z: 0.0 },
+        mat,
+    );
+    assert!(triangle.hit(&down(0.2, 0.2), 0.001, f64::INFINITY).is_some());
+    assert!(triangle.hit(&down(0.6, 0.6), 0.001, f64::INFINITY).is_none());
+}
+
+#[test]
+fn test_box_normals_face_out() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let cube = BoxShape::new(Point3 { x: 1.0, y: 1.0, z: 1.0 }, Point3 { x: -1.0, y: -1.0, z: -1.0 }, mat);
+    let axes = [
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+    ];
+    for &axis in axes.iter() {
+        for &sign in [-1.0, 1.0].iter() {
+            let outside = sign * 5.0 * axis;
+            let hit = cube.hit(&Ray::new(outside, -outside), 0.001, f64::INFINITY).unwrap();
+            assert!(hit.front_face);
+            assert_eq!(hit.normal, sign * axis);
+            assert!((hit.t - 0.8).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn test_light_sampling_pdf() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let origin = Point3 { x: 0.3, y: 0.6, z: 0.2 };
+    let shapes = [
+        Planar::xz_rect(-1.0, 1.0, -0.5, 1.0, 0.0, mat.clone()),
+        Planar::disk(Point3::new(), Vec3 { x: 0.2, y: 1.0, z: 0.0 }, 1.0, mat.clone()),
+        Planar::triangle(Point3::new(), Point3 { x: 0.0, y: 0.0, z: 1.0 }, Point3 { x: 1.0, y: 0.0, z: 0.0 }, mat),
+    ];
+    for shape in shapes.iter() {
+        // sampled directions all land on the shape
+        for _ in 0..100 {
+            let direction = shape.random_direction(origin).unwrap();
+            assert!(shape.pdf_value(origin, direction, 0.0) > 0.0);
+        }
+        // and the density over every direction adds up to 1
+        let n = 200000;
+        let sum: f64 = (0..n).map(|_| shape.pdf_value(origin, Vec3::random_in_unit_vector(), 0.0)).sum();
+        assert!((4.0 * PI * sum / n as f64 - 1.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,384 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::material::Material;
+use crate::triangle::*;
+
+
+// Stanford PLY meshes, ASCII or binary in either byte order.
+// Only the `vertex` and `face` elements are used, anything else in the file is skipped over.
+// Vertices need x, y and z and can have nx/ny/nz normals, red/green/blue colours and
+// u/v (or s/t) texture coordinates. Faces are fanned into triangles.
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+impl ScalarType {
+
+    fn parse(name: &str) -> Result<ScalarType, String> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            _ => Err(format!("unknown property type `{}`", name)),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    // type of the count, then of the items
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+
+// Returns the format, the elements and where the data starts
+fn parse_header(bytes: &[u8]) -> Result<(PlyFormat, Vec<Element>, usize), String> {
+    let marker = b"end_header";
+    let end = bytes
+        .windows(marker.len())
+        .position(|w| w == marker)
+        .ok_or("no end_header")?;
+    // the data starts after the newline that ends the header
+    let body = match bytes[end..].iter().position(|&b| b == b'\n') {
+        Some(newline) => end + newline + 1,
+        None => bytes.len(),
+    };
+    let header = std::str::from_utf8(&bytes[..end]).map_err(|_| "header is not text")?;
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(format!("unknown format `{}`", name)),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| format!("bad element count `{}`", count))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            }
+            ["property", "list", count_type, item_type, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                let kind = PropertyKind::List(ScalarType::parse(count_type)?, ScalarType::parse(item_type)?);
+                element.properties.push(Property { name: name.to_string(), kind });
+            }
+            ["property", scalar_type, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                let kind = PropertyKind::Scalar(ScalarType::parse(scalar_type)?);
+                element.properties.push(Property { name: name.to_string(), kind });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("unexpected header line `{}`", line)),
+        }
+    }
+
+    let format = format.ok_or("no format line")?;
+    Ok((format, elements, body))
+}
+
+
+// Reads the values in the body one at a time, whatever their encoding
+enum BodyReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], pos: usize, big_endian: bool },
+}
+
+impl BodyReader<'_> {
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match self {
+            BodyReader::Ascii(tokens) => {
+                let token = tokens.next().ok_or("file ends early")?;
+                token.parse::<f64>().map_err(|_| format!("bad number `{}`", token))
+            }
+            BodyReader::Binary { bytes, pos, big_endian } => {
+                let size = ty.size();
+                if *pos + size > bytes.len() {
+                    return Err("file ends early".to_string());
+                }
+                let mut raw = [0u8; 8];
+                raw[..size].copy_from_slice(&bytes[*pos..*pos + size]);
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+                *pos += size;
+
+                let value = match ty {
+                    ScalarType::I8 => raw[0] as i8 as f64,
+                    ScalarType::U8 => raw[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    ScalarType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    ScalarType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    ScalarType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    ScalarType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(raw),
+                };
+                Ok(value)
+            }
+        }
+    }
+
+    // scalars give one value, lists all of theirs
+    fn read_property(&mut self, kind: PropertyKind) -> Result<Vec<f64>, String> {
+        match kind {
+            PropertyKind::Scalar(ty) => Ok(vec![self.read(ty)?]),
+            PropertyKind::List(count_type, item_type) => {
+                let count = self.read(count_type)?;
+                if count < 0.0 {
+                    return Err("negative list length".to_string());
+                }
+                (0..count as usize).map(|_| self.read(item_type)).collect()
+            }
+        }
+    }
+}
+
+
+// Integer colours are 8 or 16 bit sRGB, float ones are taken as already linear
+fn colour_channel(value: f64, ty: PropertyKind) -> f64 {
+    let max = match ty {
+        PropertyKind::Scalar(ScalarType::U8) => 255.0,
+        PropertyKind::Scalar(ScalarType::U16) => 65535.0,
+        _ => return value,
+    };
+    let v = value / max;
+    v * v
+}
+
+pub fn load_ply(path: &Path, mat_ptr: Arc<dyn Material>) -> Result<TriangleMesh, String> {
+    let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    parse_ply(&bytes, mat_ptr).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+pub fn parse_ply(bytes: &[u8], mat_ptr: Arc<dyn Material>) -> Result<TriangleMesh, String> {
+    let (format, elements, body) = parse_header(bytes)?;
+    let mut reader = match format {
+        PlyFormat::Ascii => {
+            let text = std::str::from_utf8(&bytes[body..]).map_err(|_| "ascii body is not text")?;
+            BodyReader::Ascii(text.split_ascii_whitespace())
+        }
+        PlyFormat::BinaryLittleEndian => BodyReader::Binary { bytes, pos: body, big_endian: false },
+        PlyFormat::BinaryBigEndian => BodyReader::Binary { bytes, pos: body, big_endian: true },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colours = Vec::new();
+    let mut faces = Vec::new();
+
+    for element in elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let xyz = [element.find(&["x"]), element.find(&["y"]), element.find(&["z"])];
+                let [x, y, z] = match xyz {
+                    [Some(x), Some(y), Some(z)] => [x, y, z],
+                    _ => return Err("vertices need x, y and z".to_string()),
+                };
+                let normal = match [element.find(&["nx"]), element.find(&["ny"]), element.find(&["nz"])] {
+                    [Some(nx), Some(ny), Some(nz)] => Some([nx, ny, nz]),
+                    _ => None,
+                };
+                let colour = match [
+                    element.find(&["red", "r", "diffuse_red"]),
+                    element.find(&["green", "g", "diffuse_green"]),
+                    element.find(&["blue", "b", "diffuse_blue"]),
+                ] {
+                    [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+                    _ => None,
+                };
+                let uv = match [
+                    element.find(&["u", "s", "texture_u", "texture_s"]),
+                    element.find(&["v", "t", "texture_v", "texture_t"]),
+                ] {
+                    [Some(u), Some(v)] => Some([u, v]),
+                    _ => None,
+                };
+
+                for _ in 0..element.count {
+                    let mut values = Vec::with_capacity(element.properties.len());
+                    for property in element.properties.iter() {
+                        // a list on a vertex is read past, only its first value is kept
+                        values.push(reader.read_property(property.kind)?.first().copied().unwrap_or(0.0));
+                    }
+                    positions.push(Point3 { x: values[x], y: values[y], z: values[z] });
+                    if let Some([nx, ny, nz]) = normal {
+                        normals.push(Vec3 { x: values[nx], y: values[ny], z: values[nz] });
+                    }
+                    if let Some(channels) = colour {
+                        let [r, g, b] = channels.map(|i| colour_channel(values[i], element.properties[i].kind));
+                        colours.push(Colour { x: r, y: g, z: b });
+                    }
+                    if let Some([u, v]) = uv {
+                        uvs.push((values[u], values[v]));
+                    }
+                }
+            }
+            "face" => {
+                let indices = element.find(&["vertex_indices", "vertex_index"]).ok_or("faces need vertex_indices")?;
+                for _ in 0..element.count {
+                    for (i, property) in element.properties.iter().enumerate() {
+                        let values = reader.read_property(property.kind)?;
+                        if i != indices {
+                            continue;
+                        }
+                        if values.len() < 3 {
+                            return Err(format!("a face needs at least 3 vertices, got {}", values.len()));
+                        }
+                        // casting would quietly turn -1 or 1.5 into a real vertex
+                        if let Some(bad) = values.iter().find(|v| !(v.fract() == 0.0 && **v >= 0.0)) {
+                            return Err(format!("bad vertex index {}", bad));
+                        }
+                        let corners: Vec<usize> = values.iter().map(|&v| v as usize).collect();
+                        for j in 1..corners.len() - 1 {
+                            faces.push([corners[0], corners[j], corners[j + 1]]);
+                        }
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for property in element.properties.iter() {
+                        reader.read_property(property.kind)?;
+                    }
+                }
+            }
+        }
+    }
+    // normals and uvs are per vertex, so faces index them the same way as positions
+    let faces = faces
+        .into_iter()
+        .map(|vertices| MeshFace {
+            vertices,
+            normals: if normals.is_empty() { None } else { Some(vertices) },
+            uvs: if uvs.is_empty() { None } else { Some(vertices) },
+        })
+        .collect();
+    let mesh = TriangleMesh::new(positions, normals, uvs, faces, mat_ptr)?;
+    if colours.is_empty() {
+        Ok(mesh)
+    } else {
+        mesh.with_colours(colours)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_parse_ascii_ply() {
+    let source = "ply
+format ascii 1.0
+comment a coloured quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let mesh = parse_ply(source.as_bytes(), mat.clone()).unwrap();
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.faces.len(), 2);
+    assert_eq!(mesh.faces[1].vertices, [0, 2, 3]);
+    assert_eq!(mesh.colours[0], Colour { x: 1.0, y: 0.0, z: 0.0 });
+    assert!(mesh.normals.is_empty());
+
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n", mat.clone()).is_err());
+    let negative = source.replace("4 0 1 2 3", "4 0 1 2 -1");
+    assert!(parse_ply(negative.as_bytes(), mat).is_err());
+}
+
+#[test]
+fn test_parse_binary_ply_both_endians() {
+    let header = |format: &str| format!(
+        "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nproperty float nx\nproperty float ny\nproperty float nz\nelement face 1\nproperty list uchar uint vertex_indices\nend_header\n",
+        format
+    );
+    let vertices: [[f32; 6]; 3] = [[0.0, 0.0, 0.0, 0.0, 0.0, 1.0], [1.0, 0.0, 0.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 0.0, 0.0, 1.0]];
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+
+    for &big_endian in &[false, true] {
+        let mut bytes = header(if big_endian { "binary_big_endian" } else { "binary_little_endian" }).into_bytes();
+        for v in vertices.iter().flatten() {
+            bytes.extend_from_slice(&if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+        }
+        bytes.push(3);
+        for i in 0..3u32 {
+            bytes.extend_from_slice(&if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+
+        let mesh = parse_ply(&bytes, mat.clone()).unwrap();
+        assert_eq!(mesh.positions[1], Point3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert_eq!(mesh.normals[2], Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+        assert_eq!(mesh.faces[0], MeshFace { vertices: [0, 1, 2], normals: Some([0, 1, 2]), uvs: None });
+    }
+}
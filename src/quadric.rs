@@ -0,0 +1,446 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::*;
+use crate::sphere::{set_face_normal, get_sphere_uv};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+
+// Curved shapes beyond the sphere. Each is built around the y axis in its own space,
+// wrap them in an Instance to move and turn them.
+
+
+// Angle around the y axis, 0 to 2 pi starting at +x and turning towards +z
+fn azimuth(p: Point3) -> f64 {
+    let phi = p.z.atan2(p.x);
+    if phi < 0.0 { phi + 2.0 * PI } else { phi }
+}
+
+// Where a ray crosses a surface: t, the outward normal there, u and v
+type Crossing = (f64, Vec3, f64, f64);
+
+// The nearest of the crossings inside the ray's range
+fn nearest_hit(ray: &Ray, crossings: Vec<Crossing>, tmin: f64, tmax: f64, mat_ptr: &Arc<dyn Material>) -> Option<HitRecord> {
+    let nearest = crossings
+        .into_iter()
+        .filter(|&(t, ..)| t > tmin && t < tmax)
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())?;
+    Some(to_record(ray, nearest, mat_ptr))
+}
+
+fn to_record(ray: &Ray, (t, outward_normal, u, v): Crossing, mat_ptr: &Arc<dyn Material>) -> HitRecord {
+    let (front_face, normal) = set_face_normal(ray, outward_normal.unit_vector());
+    HitRecord {
+        p: ray.clone().at(t),
+        normal,
+        mat_ptr: mat_ptr.clone(),
+        t,
+        u,
+        v,
+        front_face,
+        colour: None,
+        sampled_light: false,
+    }
+}
+
+fn crossings_to_intervals(ray: &Ray, crossings: Vec<Crossing>, mat_ptr: &Arc<dyn Material>) -> Vec<(HitRecord, HitRecord)> {
+    pair_crossings(crossings.into_iter().map(|c| to_record(ray, c, mat_ptr)).collect())
+}
+
+// Both roots of a t^2 + b t + c, smallest first. Uses the form that avoids cancellation.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return if b.abs() < 1e-12 { Vec::new() } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (r0, r1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    if r0 < r1 { vec![r0, r1] } else { vec![r1, r0] }
+}
+
+fn eval_polynomial(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().fold(0.0, |acc, &c| acc * x + c)
+}
+
+// Real roots of a polynomial, coefficients from the highest power down, in increasing order.
+// The roots of the derivative split the line into pieces where the polynomial only rises or falls,
+// so each piece holds at most one root and bisection finds it.
+pub fn solve_polynomial(coeffs: &[f64]) -> Vec<f64> {
+    // drop vanishing leading terms
+    let scale = coeffs.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    if scale == 0.0 {
+        return Vec::new();
+    }
+    let start = coeffs.iter().position(|c| c.abs() > 1e-12 * scale).unwrap();
+    let coeffs = &coeffs[start..];
+    let degree = coeffs.len() - 1;
+
+    match degree {
+        0 => return Vec::new(),
+        1 => return vec![-coeffs[1] / coeffs[0]],
+        2 => return solve_quadratic(coeffs[0], coeffs[1], coeffs[2]),
+        _ => {}
+    }
+
+    // Cauchy's bound, every root lies inside it
+    let bound = 1.0 + coeffs[1..].iter().fold(0.0f64, |m, c| m.max((c / coeffs[0]).abs()));
+
+    let derivative: Vec<f64> = coeffs[..degree]
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c * (degree - i) as f64)
+        .collect();
+    let mut edges = vec![-bound];
+    edges.extend(solve_polynomial(&derivative).into_iter().filter(|x| x.abs() < bound));
+    edges.push(bound);
+
+    let mut roots = Vec::new();
+    for pair in edges.windows(2) {
+        let (mut lo, mut hi) = (pair[0], pair[1]);
+        let (f_lo, f_hi) = (eval_polynomial(coeffs, lo), eval_polynomial(coeffs, hi));
+        if f_lo == 0.0 {
+            roots.push(lo);
+            continue;
+        }
+        if f_lo.signum() == f_hi.signum() {
+            continue;
+        }
+        for _ in 0..100 {
+            let mid = 0.5 * (lo + hi);
+            if mid == lo || mid == hi {
+                break;
+            }
+            if eval_polynomial(coeffs, mid).signum() == f_lo.signum() {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        roots.push(0.5 * (lo + hi));
+    }
+    roots.dedup();
+    roots
+}
+
+
+// Cylinder around the y axis from y = 0 to height, optionally with end caps.
+// phi_max (degrees) cuts it down to a wedge, 360 is the whole way round.
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64,
+    pub capped: bool,
+    pub phi_max: f64,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Cylinder {
+
+    fn crossings(&self, ray: &Ray) -> Vec<Crossing> {
+        let (o, d) = (ray.orig, ray.dir);
+        let r = self.radius;
+        let phi_max = self.phi_max.to_radians();
+        let in_wedge = |p: Point3| self.phi_max >= 360.0 || azimuth(p) <= phi_max;
+
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - r * r;
+        let sides = solve_quadratic(a, b, c).into_iter().filter_map(|t| {
+            let p = ray.clone().at(t);
+            if p.y < 0.0 || p.y > self.height || !in_wedge(p) {
+                return None;
+            }
+            Some((t, Vec3 { x: p.x, y: 0.0, z: p.z }, azimuth(p) / phi_max, p.y / self.height))
+        });
+
+        let ends = [(0.0, -1.0), (self.height, 1.0)];
+        let caps = ends.iter().filter_map(|&(y, ny)| {
+            if !self.capped || d.y == 0.0 {
+                return None;
+            }
+            let t = (y - o.y) / d.y;
+            let p = ray.clone().at(t);
+            let dist_sqred = p.x * p.x + p.z * p.z;
+            if dist_sqred > r * r || !in_wedge(p) {
+                return None;
+            }
+            Some((t, Vec3 { x: 0.0, y: ny, z: 0.0 }, azimuth(p) / phi_max, dist_sqred.sqrt() / r))
+        });
+
+        sides.chain(caps).collect()
+    }
+}
+
+impl Hittable for Cylinder {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        nearest_hit(ray, self.crossings(ray), tmin, tmax, &self.mat_ptr)
+    }
+
+    // only closed with both caps on and the whole way round
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        if !self.capped || self.phi_max < 360.0 {
+            return None;
+        }
+        Some(crossings_to_intervals(ray, self.crossings(ray), &self.mat_ptr))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius;
+        Some(Aabb::new(Point3 { x: -r, y: 0.0, z: -r }, Point3 { x: r, y: self.height, z: r }))
+    }
+}
+
+
+// Cone around the y axis with its base of the given radius at y = 0 and its tip at y = height.
+// top below height cuts it off flat, leaving a frustum.
+pub struct Cone {
+    pub radius: f64,
+    pub height: f64,
+    pub top: f64,
+    pub capped: bool,
+    pub phi_max: f64,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Cone {
+
+    fn radius_at(&self, y: f64) -> f64 {
+        self.radius * (self.height - y) / self.height
+    }
+
+    fn crossings(&self, ray: &Ray) -> Vec<Crossing> {
+        let (o, d) = (ray.orig, ray.dir);
+        let h = self.height;
+        let k = (self.radius / h) * (self.radius / h);
+        let phi_max = self.phi_max.to_radians();
+        let in_wedge = |p: Point3| self.phi_max >= 360.0 || azimuth(p) <= phi_max;
+
+        // x^2 + z^2 = k (h - y)^2
+        let a = d.x * d.x + d.z * d.z - k * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k * (h - o.y) * d.y);
+        let c = o.x * o.x + o.z * o.z - k * (h - o.y) * (h - o.y);
+        let sides = solve_quadratic(a, b, c).into_iter().filter_map(|t| {
+            let p = ray.clone().at(t);
+            if p.y < 0.0 || p.y > self.top || !in_wedge(p) {
+                return None;
+            }
+            let normal = Vec3 { x: p.x, y: k * (h - p.y), z: p.z };
+            Some((t, normal, azimuth(p) / phi_max, p.y / self.top))
+        });
+
+        let ends = [(0.0, -1.0), (self.top, 1.0)];
+        let caps = ends.iter().filter_map(|&(y, ny)| {
+            let cap_radius = self.radius_at(y);
+            if !self.capped || d.y == 0.0 || cap_radius <= 0.0 {
+                return None;
+            }
+            let t = (y - o.y) / d.y;
+            let p = ray.clone().at(t);
+            let dist_sqred = p.x * p.x + p.z * p.z;
+            if dist_sqred > cap_radius * cap_radius || !in_wedge(p) {
+                return None;
+            }
+            Some((t, Vec3 { x: 0.0, y: ny, z: 0.0 }, azimuth(p) / phi_max, dist_sqred.sqrt() / cap_radius))
+        });
+
+        sides.chain(caps).collect()
+    }
+}
+
+impl Hittable for Cone {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        nearest_hit(ray, self.crossings(ray), tmin, tmax, &self.mat_ptr)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        if !self.capped || self.phi_max < 360.0 {
+            return None;
+        }
+        Some(crossings_to_intervals(ray, self.crossings(ray), &self.mat_ptr))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius;
+        Some(Aabb::new(Point3 { x: -r, y: 0.0, z: -r }, Point3 { x: r, y: self.top, z: r }))
+    }
+}
+
+
+// Ring doughnut lying in the xz plane, the tube of minor_radius runs
+// round a circle of major_radius about the origin
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Torus {
+
+    fn crossings(&self, ray: &Ray) -> Vec<Crossing> {
+        // a unit direction keeps the quartic's coefficients in a sane range
+        let scale = ray.dir.len();
+        let (o, d) = (ray.orig, ray.dir / scale);
+        let (big_r2, small_r2) = (self.major_radius * self.major_radius, self.minor_radius * self.minor_radius);
+
+        // (|p|^2 - R^2 - r^2)^2 + 4 R^2 (y^2 - r^2) = 0 along the ray
+        let e = o.len_sqred() - big_r2 - small_r2;
+        let f = o.dot(d);
+        let coeffs = [
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * big_r2 * d.y * d.y,
+            4.0 * f * e + 8.0 * big_r2 * o.y * d.y,
+            e * e - 4.0 * big_r2 * (small_r2 - o.y * o.y),
+        ];
+
+        solve_polynomial(&coeffs).into_iter().map(|s| {
+            let t = s / scale;
+            let p = ray.clone().at(t);
+            let g = p.len_sqred() - big_r2 - small_r2;
+            let normal = Vec3 { x: p.x * g, y: p.y * (g + 2.0 * big_r2), z: p.z * g };
+            // v goes round the tube, starting on the outside edge
+            let ring = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+            let theta = p.y.atan2(ring);
+            let theta = if theta < 0.0 { theta + 2.0 * PI } else { theta };
+            (t, normal, azimuth(p) / (2.0 * PI), theta / (2.0 * PI))
+        }).collect()
+    }
+}
+
+impl Hittable for Torus {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        nearest_hit(ray, self.crossings(ray), tmin, tmax, &self.mat_ptr)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        Some(crossings_to_intervals(ray, self.crossings(ray), &self.mat_ptr))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let outer = self.major_radius + self.minor_radius;
+        let r = self.minor_radius;
+        Some(Aabb::new(Point3 { x: -outer, y: -r, z: -outer }, Point3 { x: outer, y: r, z: outer }))
+    }
+}
+
+
+// Any surface a x^2 + b y^2 + c z^2 + d xy + e xz + f yz + g x + h y + i z + j = 0,
+// clipped to a box. Ellipsoids, paraboloids, hyperboloids and so on.
+// The outside is where the left hand side is positive.
+pub struct Quadric {
+    pub coeffs: [f64; 10],
+    pub clip: Aabb,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Quadric {
+
+    fn gradient(&self, p: Point3) -> Vec3 {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coeffs;
+        Vec3 {
+            x: 2.0 * a * p.x + d * p.y + e * p.z + g,
+            y: 2.0 * b * p.y + d * p.x + f * p.z + h,
+            z: 2.0 * c * p.z + e * p.x + f * p.y + i,
+        }
+    }
+}
+
+impl Hittable for Quadric {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coeffs;
+        let (o, v) = (ray.orig, ray.dir);
+
+        let qa = a * v.x * v.x + b * v.y * v.y + c * v.z * v.z + d * v.x * v.y + e * v.x * v.z + f * v.y * v.z;
+        let qb = 2.0 * (a * o.x * v.x + b * o.y * v.y + c * o.z * v.z)
+            + d * (o.x * v.y + o.y * v.x)
+            + e * (o.x * v.z + o.z * v.x)
+            + f * (o.y * v.z + o.z * v.y)
+            + g * v.x + h * v.y + i * v.z;
+        let qc = a * o.x * o.x + b * o.y * o.y + c * o.z * o.z
+            + d * o.x * o.y + e * o.x * o.z + f * o.y * o.z
+            + g * o.x + h * o.y + i * o.z + j;
+
+        let (min, max) = (self.clip.minimum, self.clip.maximum);
+        let centre = self.clip.centroid();
+        let half = 0.5 * (max - min);
+        let candidates = solve_quadratic(qa, qb, qc).into_iter().filter_map(|t| {
+            let p = ray.clone().at(t);
+            let inside = (0..3).all(|axis| p[axis] >= min[axis] && p[axis] <= max[axis]);
+            if !inside {
+                return None;
+            }
+            // spherical mapping around the middle of the clip box
+            let local = p - centre;
+            let (u, v) = get_sphere_uv(Vec3 { x: local.x / half.x, y: local.y / half.y, z: local.z / half.z }.unit_vector());
+            Some((t, self.gradient(p), u, v))
+        });
+
+        nearest_hit(ray, candidates.collect(), tmin, tmax, &self.mat_ptr)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.clip)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_solve_polynomial() {
+    // (x - 1)(x + 2)(x - 3)(x - 0.5)
+    let roots = solve_polynomial(&[1.0, -2.5, -4.0, 8.5, -3.0]);
+    let expected = [-2.0, 0.5, 1.0, 3.0];
+    assert_eq!(roots.len(), 4);
+    for (r, e) in roots.iter().zip(expected.iter()) {
+        assert!((r - e).abs() < 1e-9, "{:?}", roots);
+    }
+    // x^4 + 1 has no real roots
+    assert!(solve_polynomial(&[1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+}
+
+#[test]
+fn test_quadric_shapes_hit() {
+    let mat: Arc<dyn Material> = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let from_side = Ray::new(Point3 { x: -5.0, y: 0.5, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let from_above = Ray::new(Point3 { x: 0.0, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+
+    let cylinder = Cylinder { radius: 1.0, height: 1.0, capped: true, phi_max: 360.0, mat_ptr: mat.clone() };
+    let hit = cylinder.hit(&from_side, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 4.0).abs() < 1e-9);
+    assert_eq!(hit.normal, Vec3 { x: -1.0, y: 0.0, z: 0.0 });
+    let hit = cylinder.hit(&from_above, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 4.0).abs() < 1e-9 && hit.front_face);
+
+    let open = Cylinder { capped: false, ..cylinder };
+    let hit = open.hit(&from_above, 0.001, f64::INFINITY);
+    assert!(hit.is_none());
+
+    let cone = Cone { radius: 1.0, height: 1.0, top: 1.0, capped: true, phi_max: 360.0, mat_ptr: mat.clone() };
+    // halfway up the radius is 0.5
+    let hit = cone.hit(&from_side, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 4.5).abs() < 1e-9);
+    assert!(hit.normal.y > 0.0 && hit.normal.x < 0.0);
+
+    let torus = Torus { major_radius: 2.0, minor_radius: 0.5, mat_ptr: mat.clone() };
+    let along_x = Ray::new(Point3 { x: -5.0, y: 0.0, z: 0.0 }, Vec3 { x: 2.0, y: 0.0, z: 0.0 });
+    let hit = torus.hit(&along_x, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 1.25).abs() < 1e-9, "{}", hit.t);
+    assert!((hit.normal - Vec3 { x: -1.0, y: 0.0, z: 0.0 }).len() < 1e-9);
+    // straight down through the hole misses
+    assert!(torus.hit(&from_above, 0.001, f64::INFINITY).is_none());
+
+    // unit sphere as a quadric
+    let clip = Aabb::new(Point3 { x: -1.0, y: -1.0, z: -1.0 }, Point3 { x: 1.0, y: 1.0, z: 1.0 });
+    let sphere = Quadric { coeffs: [1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0], clip, mat_ptr: mat };
+    let hit = sphere.hit(&from_above, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 4.0).abs() < 1e-9 && hit.front_face);
+}
@@ -0,0 +1,37 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use std::cell::RefCell;
+
+// Every thread gets its own generator, seeded from entropy until someone calls reseed().
+// Reseeding per row (rather than per thread) is what keeps a --seed render reproducible,
+// since rayon gives no guarantees about which thread ends up with which row.
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+pub fn reseed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+// [0,1)
+pub fn random_double() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen::<f64>())
+}
+
+// [min,max)
+pub fn random_range(min: f64, max: f64) -> f64 {
+    min + (max - min) * random_double()
+}
+
+
+
+// TEST
+#[test]
+fn test_reseed_repeats_sequence() {
+    reseed(42);
+    let first: Vec<f64> = (0..8).map(|_| random_double()).collect();
+    reseed(42);
+    let second: Vec<f64> = (0..8).map(|_| random_double()).collect();
+    assert_eq!(first, second);
+    assert!(first.iter().all(|x| (0.0..1.0).contains(x)));
+}
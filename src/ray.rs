@@ -4,14 +4,21 @@ use crate::vec3::*;
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    // when the ray was sent, within the camera's shutter, for things that move
+    pub time: f64,
 }
 
 impl Ray {
  
     pub fn new(o: Point3, d: Vec3) -> Ray {
+        Ray::with_time(o, d, 0.0)
+    }
+
+    pub fn with_time(o: Point3, d: Vec3, time: f64) -> Ray {
         Ray {
                 dir: d,
                 orig: o,
+                time,
         }
     }
 
@@ -19,3 +26,4 @@ impl Ray {
         self.orig + self.dir * t
     }
 }
+
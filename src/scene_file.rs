@@ -0,0 +1,1388 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::vec3::*;
+use crate::hittable::*;
+use crate::sphere::*;
+use crate::triangle::*;
+use crate::planar::*;
+use crate::quadric::*;
+use crate::aabb::*;
+use crate::obj::*;
+use crate::ply::*;
+use crate::stl::*;
+use crate::transform::*;
+use crate::csg::*;
+use crate::sdf::*;
+use crate::heightfield::*;
+use crate::medium::*;
+use crate::volume::*;
+use crate::material::*;
+use crate::light::*;
+use crate::camera::*;
+use crate::scenes::*;
+use crate::background::*;
+use crate::environment::*;
+use crate::texture::*;
+use crate::image_io::read_image;
+use crate::perlin::*;
+
+
+// Scene files are TOML, e.g.
+//
+//     [camera]
+//     lookfrom = [13, 2, 3]
+//     lookat = [0, 0, 0]
+//     vfov = 20
+//     aperture = 0.1
+//
+//     [materials.ground]
+//     type = "lambertian"
+//     albedo = [0.5, 0.5, 0.5]
+//
+//     [[objects]]
+//     type = "sphere"
+//     center = [0, -1000, 0]
+//     radius = 1000
+//     material = "ground"
+//
+// Materials are declared once by name and objects refer to them by that name.
+// Textures work the same way, anywhere an albedo colour is accepted a texture name can be given instead:
+//
+//     [textures.board]
+//     type = "checker"
+//     even = [0.2, 0.3, 0.1]
+//     odd = [0.9, 0.9, 0.9]
+//     scale = 0.5
+//
+// Image paths are relative to the scene file.
+// The procedural textures (noise, marble, wood) take a `seed` so the same file always gives the same pattern.
+// Besides spheres there are flat shapes: `triangle` (three `vertices`), `quad` (corner `q` and
+// edges `u`, `v`), `rect` (axis aligned, `plane = "xz"`, `min`, `max` and the height `k`) and `disk`,
+// plus `box` given two opposite corners. The curved shapes are `cylinder`, `cone` (both with a `base`,
+// `axis`, `radius` and `height`), `torus` and `quadric`, see ObjectDesc for their parameters. Indexed meshes take face indices starting at 0:
+//
+//     [[objects]]
+//     type = "mesh"
+//     vertices = [[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0]]
+//     faces = [[0, 1, 2], [0, 2, 3]]
+//     material = "ground"
+//
+// A mesh can also give per vertex `normals` for smooth shading and `uvs` for textures.
+// Models can be loaded from OBJ files, using the materials from their MTL files:
+//
+//     [[objects]]
+//     type = "obj"
+//     path = "models/teapot.obj"
+//     material = "ground"      # optional, for faces without a material of their own
+//
+// `ply` and `stl` objects load those formats the same way, but always need a `material`.
+// Vertex colours in a PLY file tint the material, so use a white albedo to see them as they are.
+//
+// Shapes are objects declared by name instead of placed in the world. Instances put a copy of
+// one in the world, sharing its geometry, with an optional scale, rotation and translation:
+//
+//     [shapes.cube]
+//     type = "obj"
+//     path = "models/cube.obj"
+//
+//     [[objects]]
+//     type = "instance"
+//     shape = "cube"
+//     scale = 2                # or [x, y, z]
+//     rotate = [0, 45, 0]      # degrees about x, then y, then z
+//     translate = [1, 0, -2]
+//
+// Shapes can be combined with `csg` objects, which can be shapes themselves to build up more
+// complex solids. Only closed shapes work: spheres, boxes, tori, capped cylinders and cones,
+// instances of those and other csg shapes.
+//
+//     [[objects]]
+//     type = "csg"
+//     operation = "difference"     # or "union", "intersection"
+//     left = "cube"
+//     right = "ball"
+//
+// Signed distance fields are `sdf` objects, built from nodes nested as inline tables: `sphere`,
+// `box` (with optional `rounding`), `torus`, `union` and `subtraction` (of `a` and `b`, blended
+// over `smooth`), `repeat` and `twist` (of a `shape`) and the `mandelbulb` fractal.
+// Fields that repeat forever also need a `min` and `max` to be traced within:
+//
+//     [[objects]]
+//     type = "sdf"
+//     material = "clay"
+//     sdf = { type = "union", smooth = 0.3, a = { type = "sphere", radius = 1 }, b = { type = "box", center = [1, 0, 0], size = [1, 1, 1] } }
+//
+// Terrain comes from `heightfield` objects, filling the box from `min` to `min + size` with
+// heights taken from a grey `image` (its top edge at the far, +z, side) or from `noise`.
+// Textures are stretched over the whole of it:
+//
+//     [[objects]]
+//     type = "heightfield"
+//     min = [-10, 0, -10]
+//     size = [20, 3, 20]
+//     noise = { seed = 3, scale = 4, resolution = [256, 256] }    # or image = "heights.png"
+//     material = "grass"
+//
+// Smoke and fog are `medium` objects filling a named shape with a `density`, scattering light
+// with their `material`, which would usually be an `isotropic` one:
+//
+//     [materials.smoke]
+//     type = "isotropic"
+//     albedo = [0.8, 0.8, 0.8]
+//
+//     [[objects]]
+//     type = "medium"
+//     boundary = "ball"
+//     density = 0.5
+//     material = "smoke"
+//
+// Volumes whose density changes from place to place fill a box, and take their density from
+// either a voxel `grid` file (see VoxelGrid for the format) or fractal `noise`, scaled by `density`:
+//
+//     [[objects]]
+//     type = "volume"
+//     min = [-1, 0, -1]
+//     max = [1, 2, 1]
+//     density = 4
+//     noise = { seed = 1, scale = 3, bias = 0.4, falloff = 1 }    # or grid = "models/smoke.vox"
+//     material = "smoke"
+//
+// Things can move while the camera's shutter is open, which blurs them. A sphere given a
+// `center_end`, or an instance given any of `scale_end`, `rotate_end` and `translate_end`,
+// moves from where it starts to there between the times 0 and 1. The camera's `shutter`
+// is [0, 1] unless given, so it sees the whole move.
+//
+// Spheres, triangles, quads, rects and disks with a `diffuse_light` material are sampled directly
+// as lights, which makes small lights far less noisy. Other glowing objects still light the scene,
+// but only when bounces happen to find them.
+//
+// Lights that are not objects at all, and so are never seen, only their light, go in `lights`.
+// `point` and `spot` lights have a `position` and fall off with the square of the distance,
+// spots shine along a `direction` out to an `angle` in degrees, softened over the last `falloff`
+// degrees. A `directional` light is a sun, lighting everything from one `direction`:
+//
+//     [[lights]]
+//     type = "spot"
+//     position = [0, 5, 0]
+//     direction = [0, -1, 0]
+//     intensity = [20, 20, 20]
+//     angle = 30
+//     falloff = 5
+//
+// An optional top level `background` is "black", "gradient" or an [r, g, b] colour,
+// or an HDR environment image wrapped around the scene that lights it as well:
+//
+//     background = { image = "studio.hdr", rotation = 90, intensity = 1.5 }
+//
+// Environment images are latitude longitude .hdr or .pfm files with the sky along the top.
+
+
+#[derive(Debug, PartialEq)]
+pub struct SceneError {
+    // 1 based, None when the problem is not tied to a place in the file
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Spanned<CameraDesc>,
+    #[serde(default)]
+    background: Option<Spanned<BackgroundDesc>>,
+    #[serde(default)]
+    textures: BTreeMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    shapes: BTreeMap<String, Spanned<ObjectDesc>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDesc>>,
+    #[serde(default)]
+    lights: Vec<Spanned<LightDesc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    // vertical field of view in degrees
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    // defaults to the distance between lookfrom and lookat
+    focus_dist: Option<f64>,
+    // when the shutter opens and closes, moving objects move over the time from 0 to 1
+    #[serde(default = "default_shutter")]
+    shutter: [f64; 2],
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_shutter() -> [f64; 2] {
+    [0.0, 1.0]
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Named(String),
+    Colour([f64; 3]),
+    Environment(EnvironmentDesc),
+}
+
+// An equirectangular .hdr or .pfm image, turned by rotation degrees about the vertical
+// and with its brightness scaled by intensity
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    image: PathBuf,
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default = "default_scale")]
+    intensity: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColourOrTexture {
+    Colour([f64; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid { colour: [f64; 3] },
+    Checker { even: ColourOrTexture, odd: ColourOrTexture, #[serde(default = "default_checker_scale")] scale: f64 },
+    Image { path: PathBuf },
+    Noise {
+        #[serde(default = "default_scale")] scale: f64,
+        #[serde(default = "default_noise_octaves")] octaves: u32,
+        #[serde(default)] seed: u64,
+    },
+    Marble {
+        #[serde(default = "default_scale")] scale: f64,
+        #[serde(default = "default_marble_turbulence")] turbulence: f64,
+        #[serde(default = "default_turbulence_octaves")] octaves: u32,
+        #[serde(default = "default_light")] light: [f64; 3],
+        #[serde(default)] dark: [f64; 3],
+        #[serde(default)] seed: u64,
+    },
+    Wood {
+        #[serde(default = "default_wood_rings")] scale: f64,
+        #[serde(default = "default_wood_turbulence")] turbulence: f64,
+        #[serde(default = "default_noise_octaves")] octaves: u32,
+        #[serde(default = "default_wood_light")] light: [f64; 3],
+        #[serde(default = "default_wood_dark")] dark: [f64; 3],
+        #[serde(default)] seed: u64,
+    },
+}
+
+fn default_checker_scale() -> f64 {
+    1.0
+}
+fn default_scale() -> f64 {
+    1.0
+}
+fn default_noise_octaves() -> u32 {
+    1
+}
+fn default_turbulence_octaves() -> u32 {
+    7
+}
+fn default_marble_turbulence() -> f64 {
+    10.0
+}
+fn default_light() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+fn default_wood_rings() -> f64 {
+    8.0
+}
+fn default_wood_turbulence() -> f64 {
+    0.4
+}
+fn default_wood_light() -> [f64; 3] {
+    [0.75, 0.55, 0.33]
+}
+fn default_wood_dark() -> [f64; 3] {
+    [0.4, 0.25, 0.12]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo: ColourOrTexture },
+    Metal { albedo: ColourOrTexture, #[serde(default)] fuzz: f64 },
+    Dielectric { ir: f64 },
+    DiffuseLight { emit: [f64; 3] },
+    // for volumes, scatters the same in every direction
+    Isotropic { albedo: ColourOrTexture },
+}
+
+// Lights that are not objects, intensity is the colour seen 1 unit away, or anywhere for a sun
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Point { position: [f64; 3], intensity: [f64; 3] },
+    // angle is how far out from direction the cone reaches in degrees, the last falloff degrees fading
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        angle: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+    // direction is the way the light travels
+    Directional { direction: [f64; 3], intensity: [f64; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    // with `center_end` the sphere moves from center to there over the time from 0 to 1
+    Sphere { center: [f64; 3], #[serde(default)] center_end: Option<[f64; 3]>, radius: f64, material: String },
+    Triangle { vertices: [[f64; 3]; 3], material: String },
+    // parallelogram from corner q along the edges u and v, facing u x v
+    Quad { q: [f64; 3], u: [f64; 3], v: [f64; 3], material: String },
+    // axis aligned rectangle in the given plane ("xy", "xz" or "yz") at height k along the remaining axis
+    Rect { plane: String, min: [f64; 2], max: [f64; 2], k: f64, material: String },
+    Disk { center: [f64; 3], normal: [f64; 3], radius: f64, material: String },
+    Box { min: [f64; 3], max: [f64; 3], material: String },
+    // cylinders and cones stand on `base` and run up along `axis` for `height`
+    Cylinder {
+        #[serde(default)]
+        base: [f64; 3],
+        #[serde(default = "default_axis")]
+        axis: [f64; 3],
+        radius: f64,
+        height: f64,
+        #[serde(default = "default_capped")]
+        capped: bool,
+        #[serde(default = "default_phi_max")]
+        phi_max: f64,
+        material: String,
+    },
+    Cone {
+        #[serde(default)]
+        base: [f64; 3],
+        #[serde(default = "default_axis")]
+        axis: [f64; 3],
+        radius: f64,
+        height: f64,
+        // cut off flat below the tip
+        #[serde(default)]
+        top: Option<f64>,
+        #[serde(default = "default_capped")]
+        capped: bool,
+        #[serde(default = "default_phi_max")]
+        phi_max: f64,
+        material: String,
+    },
+    // lies flat around `axis`
+    Torus {
+        center: [f64; 3],
+        #[serde(default = "default_axis")]
+        axis: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+        material: String,
+    },
+    // the ten coefficients of x^2, y^2, z^2, xy, xz, yz, x, y, z and 1, clipped to the box min to max
+    Quadric { coefficients: [f64; 10], min: [f64; 3], max: [f64; 3], material: String },
+    Mesh {
+        vertices: Vec<[f64; 3]>,
+        faces: Vec<[usize; 3]>,
+        // per vertex, in the same order as `vertices`
+        #[serde(default)]
+        normals: Option<Vec<[f64; 3]>>,
+        #[serde(default)]
+        uvs: Option<Vec<[f64; 2]>>,
+        material: String,
+    },
+    // material is for faces the model does not give one of its own
+    Obj { path: String, #[serde(default)] material: Option<String> },
+    Ply { path: String, material: String },
+    Stl { path: String, material: String },
+    // a distance field shape, traced within `min` and `max`, which can be left out when the field has bounds of its own
+    Sdf {
+        sdf: SdfDesc,
+        #[serde(default)]
+        min: Option<[f64; 3]>,
+        #[serde(default)]
+        max: Option<[f64; 3]>,
+        material: String,
+    },
+    // terrain over the box from `min` to `min + size`, heights from an `image` or `noise`
+    Heightfield {
+        min: [f64; 3],
+        size: [f64; 3],
+        #[serde(default)]
+        image: Option<PathBuf>,
+        #[serde(default)]
+        noise: Option<HeightNoiseDesc>,
+        material: String,
+    },
+    // fog filling the named shape, scattering with the material (usually isotropic)
+    Medium { boundary: String, density: f64, material: String },
+    // a box of varying density, from a voxel `grid` file or `noise`, the field's values times `density`
+    Volume {
+        min: [f64; 3],
+        max: [f64; 3],
+        #[serde(default = "default_density")]
+        density: f64,
+        #[serde(default)]
+        grid: Option<String>,
+        #[serde(default)]
+        noise: Option<NoiseFieldDesc>,
+        material: String,
+    },
+    // combines two closed shapes: "union", "intersection" or "difference" (left minus right)
+    Csg { operation: String, left: String, right: String },
+    // a named shape, scaled then rotated (degrees about x, then y, then z) then translated.
+    // Any of the `_end` ones make it move there over the time from 0 to 1
+    Instance {
+        shape: String,
+        #[serde(default)]
+        scale: Option<ScaleDesc>,
+        #[serde(default)]
+        rotate: Option<[f64; 3]>,
+        #[serde(default)]
+        translate: Option<[f64; 3]>,
+        #[serde(default)]
+        scale_end: Option<ScaleDesc>,
+        #[serde(default)]
+        rotate_end: Option<[f64; 3]>,
+        #[serde(default)]
+        translate_end: Option<[f64; 3]>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    PerAxis([f64; 3]),
+}
+
+
+// Distance field nodes, nested as inline tables
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SdfDesc {
+    Sphere { #[serde(default)] center: [f64; 3], radius: f64 },
+    // `rounding` rounds off the edges, keeping the overall size
+    Box { #[serde(default)] center: [f64; 3], size: [f64; 3], #[serde(default)] rounding: f64 },
+    // lying flat, around the y axis
+    Torus { #[serde(default)] center: [f64; 3], major_radius: f64, minor_radius: f64 },
+    // `smooth` blends the two over that distance
+    Union { a: Box<SdfDesc>, b: Box<SdfDesc>, #[serde(default)] smooth: f64 },
+    // a with b cut out of it
+    Subtraction { a: Box<SdfDesc>, b: Box<SdfDesc>, #[serde(default)] smooth: f64 },
+    // copies every `period` along each axis, 0 for no copies along that axis
+    Repeat { shape: Box<SdfDesc>, period: [f64; 3] },
+    // degrees of twist about the y axis per unit of height
+    Twist { shape: Box<SdfDesc>, rate: f64 },
+    Mandelbulb { #[serde(default = "default_bulb_power")] power: f64, #[serde(default = "default_bulb_iterations")] iterations: u32 },
+}
+
+fn default_bulb_power() -> f64 {
+    8.0
+}
+fn default_bulb_iterations() -> u32 {
+    12
+}
+
+fn build_sdf(desc: &SdfDesc) -> Result<Arc<dyn Sdf>, String> {
+    let sdf: Arc<dyn Sdf> = match desc {
+        SdfDesc::Sphere { center, radius } => {
+            if *radius <= 0.0 {
+                return Err(format!("sdf sphere radius must be positive, got {}", radius));
+            }
+            Arc::new(SdfSphere { center: vec3(*center), radius: *radius })
+        }
+        SdfDesc::Box { center, size, rounding } => {
+            let half_size = 0.5 * vec3(*size);
+            if half_size.x <= 0.0 || half_size.y <= 0.0 || half_size.z <= 0.0 {
+                return Err("sdf box size must be positive".to_string());
+            }
+            if *rounding < 0.0 || *rounding > half_size.x.min(half_size.y).min(half_size.z) {
+                return Err(format!("sdf box rounding must be between 0 and half its smallest side, got {}", rounding));
+            }
+            Arc::new(SdfBox { center: vec3(*center), half_size, radius: *rounding })
+        }
+        SdfDesc::Torus { center, major_radius, minor_radius } => {
+            if *minor_radius <= 0.0 || *major_radius <= 0.0 {
+                return Err("sdf torus radii must be positive".to_string());
+            }
+            Arc::new(SdfTorus { center: vec3(*center), major_radius: *major_radius, minor_radius: *minor_radius })
+        }
+        SdfDesc::Union { a, b, smooth } => Arc::new(SmoothUnion { a: build_sdf(a)?, b: build_sdf(b)?, k: *smooth }),
+        SdfDesc::Subtraction { a, b, smooth } => Arc::new(SmoothSubtraction { a: build_sdf(a)?, b: build_sdf(b)?, k: *smooth }),
+        SdfDesc::Repeat { shape, period } => {
+            if period.iter().any(|p| *p < 0.0) {
+                return Err("sdf repeat period cannot be negative".to_string());
+            }
+            Arc::new(Repeat { shape: build_sdf(shape)?, period: vec3(*period) })
+        }
+        SdfDesc::Twist { shape, rate } => Arc::new(Twist::new(build_sdf(shape)?, rate.to_radians())),
+        SdfDesc::Mandelbulb { power, iterations } => {
+            if *power < 2.0 {
+                return Err(format!("mandelbulb power must be at least 2, got {}", power));
+            }
+            Arc::new(Mandelbulb { power: *power, iterations: *iterations })
+        }
+    };
+    Ok(sdf)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HeightNoiseDesc {
+    #[serde(default)] seed: u64,
+    #[serde(default = "default_cloud_scale")] scale: f64,
+    #[serde(default = "default_cloud_octaves")] octaves: u32,
+    // how many heights along x and z
+    #[serde(default = "default_height_resolution")] resolution: [usize; 2],
+}
+
+fn default_height_resolution() -> [usize; 2] {
+    [256, 256]
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoiseFieldDesc {
+    #[serde(default)] seed: u64,
+    #[serde(default = "default_cloud_scale")] scale: f64,
+    #[serde(default = "default_cloud_octaves")] octaves: u32,
+    #[serde(default)] bias: f64,
+    #[serde(default)] falloff: f64,
+}
+
+fn default_density() -> f64 {
+    1.0
+}
+fn default_cloud_scale() -> f64 {
+    4.0
+}
+fn default_cloud_octaves() -> u32 {
+    5
+}
+
+fn default_axis() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+fn default_phi_max() -> f64 {
+    360.0
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3 { x: v[0], y: v[1], z: v[2] }
+}
+
+// Turns a byte offset into the source into a 1 based line number
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+fn error_at<T>(source: &str, spanned: &Spanned<T>, message: String) -> SceneError {
+    SceneError { line: Some(line_of(source, spanned.span().start)), message }
+}
+
+
+pub fn load_scene(path: &Path, aspect_ratio: f64) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path).map_err(|err| SceneError {
+        line: None,
+        message: format!("could not read {}: {}", path.display(), err),
+    })?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    parse_scene(&source, base_dir, aspect_ratio)
+}
+
+// base_dir is where relative paths inside the scene are looked up from
+pub fn parse_scene(source: &str, base_dir: &Path, aspect_ratio: f64) -> Result<Scene, SceneError> {
+    let desc: SceneDesc = toml::from_str(source).map_err(|err| SceneError {
+        line: err.span().map(|span| line_of(source, span.start)),
+        message: err.message().to_string(),
+    })?;
+
+    let camera = build_camera(source, &desc.camera, aspect_ratio)?;
+
+    let background = match &desc.background {
+        None => Background::default(),
+        Some(bg) => match bg.get_ref() {
+            BackgroundDesc::Colour(rgb) => Background::Solid(vec3(*rgb)),
+            BackgroundDesc::Named(name) => match name.as_str() {
+                "black" => Background::Solid(Colour::new()),
+                "gradient" => Background::Gradient,
+                _ => return Err(error_at(source, bg, format!("unknown background `{}`, expected black, gradient, [r, g, b] or an image", name))),
+            },
+            BackgroundDesc::Environment(env) => {
+                if env.intensity < 0.0 {
+                    return Err(error_at(source, bg, "environment intensity cannot be negative".to_string()));
+                }
+                let map = EnvironmentMap::load(&base_dir.join(&env.image), env.rotation, env.intensity)
+                    .map_err(|message| error_at(source, bg, message))?;
+                Background::Environment(Arc::new(map))
+            }
+        },
+    };
+
+    let mut textures = Textures {
+        source,
+        base_dir,
+        descs: &desc.textures,
+        built: BTreeMap::new(),
+        in_progress: Vec::new(),
+    };
+
+    let mut materials: BTreeMap<&str, Arc<dyn Material>> = BTreeMap::new();
+    for (name, mat) in desc.materials.iter() {
+        materials.insert(name, build_material(source, mat, &mut textures)?);
+    }
+
+    let mut shapes = Shapes {
+        source,
+        base_dir,
+        descs: &desc.shapes,
+        materials: &materials,
+        built: BTreeMap::new(),
+        in_progress: Vec::new(),
+    };
+    // build every shape, so mistakes in ones nothing uses yet are still reported
+    for (name, shape) in desc.shapes.iter() {
+        shapes.get(name, line_of(source, shape.span().start))?;
+    }
+
+    let mut world = HittableList::new();
+    let mut lights = HittableList::new();
+    for object in desc.objects.iter() {
+        let hittable = build_object(object, &mut shapes)?;
+        if is_light(object.get_ref(), &desc.materials) {
+            lights.add(hittable.clone());
+            world.add(Arc::new(SampledLight { object: hittable }));
+        } else {
+            world.add(hittable);
+        }
+    }
+
+    let mut delta_lights = Vec::new();
+    for light in desc.lights.iter() {
+        delta_lights.push(build_light(source, light)?);
+    }
+
+    Ok(Scene {
+        world: Arc::new(world.into_bvh()),
+        lights: Arc::new(lights),
+        delta_lights,
+        camera,
+        background,
+    })
+}
+
+
+// Glowing objects of the kinds that know how to be sampled, which are gathered up as the scene's lights
+fn is_light(object: &ObjectDesc, materials: &BTreeMap<String, Spanned<MaterialDesc>>) -> bool {
+    let material = match object {
+        ObjectDesc::Sphere { center_end: None, radius, material, .. } if *radius > 0.0 => material,
+        ObjectDesc::Triangle { material, .. }
+        | ObjectDesc::Quad { material, .. }
+        | ObjectDesc::Rect { material, .. }
+        | ObjectDesc::Disk { material, .. } => material,
+        _ => return false,
+    };
+    matches!(materials.get(material).map(|m| m.get_ref()), Some(MaterialDesc::DiffuseLight { .. }))
+}
+
+
+fn build_light(source: &str, light: &Spanned<LightDesc>) -> Result<Arc<dyn Light>, SceneError> {
+    let invalid = |message: &str| Err(error_at(source, light, message.to_string()));
+    Ok(match light.get_ref() {
+        LightDesc::Point { position, intensity } => Arc::new(PointLight { position: vec3(*position), intensity: vec3(*intensity) }),
+        LightDesc::Spot { position, direction, intensity, angle, falloff } => {
+            if vec3(*direction).len_sqred() == 0.0 {
+                return invalid("spot light direction cannot be zero");
+            }
+            if !(*angle > 0.0 && *angle <= 180.0) {
+                return invalid("spot light angle must be more than 0 and at most 180 degrees");
+            }
+            if !(0.0..=*angle).contains(falloff) {
+                return invalid("spot light falloff must be between 0 and its angle");
+            }
+            Arc::new(SpotLight {
+                position: vec3(*position),
+                direction: vec3(*direction),
+                intensity: vec3(*intensity),
+                angle: *angle,
+                falloff: *falloff,
+            })
+        }
+        LightDesc::Directional { direction, intensity } => {
+            if vec3(*direction).len_sqred() == 0.0 {
+                return invalid("directional light direction cannot be zero");
+            }
+            Arc::new(DirectionalLight { direction: vec3(*direction), intensity: vec3(*intensity) })
+        }
+    })
+}
+
+
+fn build_camera(source: &str, camera: &Spanned<CameraDesc>, aspect_ratio: f64) -> Result<Camera, SceneError> {
+    let desc = camera.get_ref();
+    let lookfrom = vec3(desc.lookfrom);
+    let lookat = vec3(desc.lookat);
+    let vup = vec3(desc.vup);
+
+    let invalid = |message: &str| Err(error_at(source, camera, format!("camera: {}", message)));
+    if desc.vfov <= 0.0 || desc.vfov >= 180.0 {
+        return invalid("vfov must be between 0 and 180 degrees");
+    }
+    if desc.aperture < 0.0 {
+        return invalid("aperture cannot be negative");
+    }
+    if (lookfrom - lookat).len_sqred() == 0.0 {
+        return invalid("lookfrom and lookat must be different points");
+    }
+    if vup.cross(lookfrom - lookat).len_sqred() == 0.0 {
+        return invalid("vup cannot be parallel to the view direction");
+    }
+
+    let focus_dist = desc.focus_dist.unwrap_or_else(|| (lookfrom - lookat).len());
+    if focus_dist <= 0.0 {
+        return invalid("focus_dist must be positive");
+    }
+    let [open, close] = desc.shutter;
+    if close < open {
+        return invalid("shutter cannot close before it opens");
+    }
+
+    Ok(Camera::new(lookfrom, lookat, vup, desc.vfov, aspect_ratio, desc.aperture, focus_dist).with_shutter(open, close))
+}
+
+// Textures can be built out of other textures, so they are built on demand
+// the first time something refers to them, catching any loops on the way.
+struct Textures<'a> {
+    source: &'a str,
+    base_dir: &'a Path,
+    descs: &'a BTreeMap<String, Spanned<TextureDesc>>,
+    built: BTreeMap<String, Arc<dyn Texture>>,
+    in_progress: Vec<String>,
+}
+
+impl<'a> Textures<'a> {
+
+    // line is where the reference was made, for reporting unknown names
+    fn resolve(&mut self, value: &ColourOrTexture, line: usize) -> Result<Arc<dyn Texture>, SceneError> {
+        match value {
+            ColourOrTexture::Colour(rgb) => Ok(Arc::new(SolidColour::new(vec3(*rgb)))),
+            ColourOrTexture::Texture(name) => self.get(name, line),
+        }
+    }
+
+    fn get(&mut self, name: &str, line: usize) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(texture) = self.built.get(name) {
+            return Ok(texture.clone());
+        }
+        let desc = match self.descs.get(name) {
+            Some(desc) => desc,
+            None => return Err(SceneError { line: Some(line), message: format!("unknown texture `{}`", name) }),
+        };
+        if self.in_progress.iter().any(|n| n == name) {
+            return Err(error_at(self.source, desc, format!("texture `{}` refers back to itself", name)));
+        }
+
+        self.in_progress.push(name.to_string());
+        let desc_line = line_of(self.source, desc.span().start);
+        let texture: Arc<dyn Texture> = match desc.get_ref() {
+            TextureDesc::Solid { colour } => Arc::new(SolidColour::new(vec3(*colour))),
+            TextureDesc::Checker { even, odd, scale } => {
+                if *scale <= 0.0 {
+                    return Err(error_at(self.source, desc, format!("checker scale must be positive, got {}", scale)));
+                }
+                Arc::new(CheckerTexture {
+                    even: self.resolve(even, desc_line)?,
+                    odd: self.resolve(odd, desc_line)?,
+                    scale: *scale,
+                })
+            }
+            TextureDesc::Image { path } => {
+                let texture = ImageTexture::load(&self.base_dir.join(path))
+                    .map_err(|message| error_at(self.source, desc, message))?;
+                Arc::new(texture)
+            }
+            TextureDesc::Noise { scale, octaves, seed } => Arc::new(NoiseTexture {
+                noise: Perlin::new(*seed),
+                scale: *scale,
+                octaves: *octaves,
+            }),
+            TextureDesc::Marble { scale, turbulence, octaves, light, dark, seed } => Arc::new(MarbleTexture {
+                noise: Perlin::new(*seed),
+                scale: *scale,
+                turbulence: *turbulence,
+                octaves: *octaves,
+                light: vec3(*light),
+                dark: vec3(*dark),
+            }),
+            TextureDesc::Wood { scale, turbulence, octaves, light, dark, seed } => Arc::new(WoodTexture {
+                noise: Perlin::new(*seed),
+                scale: *scale,
+                turbulence: *turbulence,
+                octaves: *octaves,
+                light: vec3(*light),
+                dark: vec3(*dark),
+            }),
+        };
+        self.in_progress.pop();
+
+        self.built.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+}
+
+fn build_material(source: &str, mat: &Spanned<MaterialDesc>, textures: &mut Textures) -> Result<Arc<dyn Material>, SceneError> {
+    let line = line_of(source, mat.span().start);
+    let material: Arc<dyn Material> = match mat.get_ref() {
+        MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian { albedo: textures.resolve(albedo, line)? }),
+        MaterialDesc::Metal { albedo, fuzz } => {
+            if !(0.0..=1.0).contains(fuzz) {
+                return Err(error_at(source, mat, format!("metal fuzz must be between 0 and 1, got {}", fuzz)));
+            }
+            Arc::new(Metal { albedo: textures.resolve(albedo, line)?, fuzz: *fuzz })
+        }
+        MaterialDesc::Dielectric { ir } => {
+            if *ir <= 0.0 {
+                return Err(error_at(source, mat, format!("dielectric ir must be positive, got {}", ir)));
+            }
+            Arc::new(Dielectric { ir: *ir })
+        }
+        MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight { emit: vec3(*emit) }),
+        MaterialDesc::Isotropic { albedo } => Arc::new(Isotropic { albedo: textures.resolve(albedo, line)? }),
+    };
+    Ok(material)
+}
+
+// Moves a shape built around the y axis through the origin so it sits at origin, along axis
+fn place_on_axis(
+    source: &str,
+    object: &Spanned<ObjectDesc>,
+    shape: Arc<dyn Hittable>,
+    origin: [f64; 3],
+    axis: [f64; 3],
+) -> Result<Arc<dyn Hittable>, SceneError> {
+    if vec3(axis).len_sqred() == 0.0 {
+        return Err(error_at(source, object, "axis cannot be zero".to_string()));
+    }
+    let transform = Mat4::translate(vec3(origin)) * Mat4::align_y(vec3(axis));
+    if transform == Mat4::identity() {
+        return Ok(shape);
+    }
+    let instance = Instance::new(shape, transform).map_err(|message| error_at(source, object, message))?;
+    Ok(Arc::new(instance))
+}
+
+// Named shapes are built the first time something uses them, then shared
+struct Shapes<'a> {
+    source: &'a str,
+    base_dir: &'a Path,
+    descs: &'a BTreeMap<String, Spanned<ObjectDesc>>,
+    materials: &'a BTreeMap<&'a str, Arc<dyn Material>>,
+    built: BTreeMap<String, Arc<dyn Hittable>>,
+    in_progress: Vec<String>,
+}
+
+impl<'a> Shapes<'a> {
+
+    // line is where the reference was made, for reporting unknown names
+    fn get(&mut self, name: &str, line: usize) -> Result<Arc<dyn Hittable>, SceneError> {
+        if let Some(shape) = self.built.get(name) {
+            return Ok(shape.clone());
+        }
+        let desc = match self.descs.get(name) {
+            Some(desc) => desc,
+            None => return Err(SceneError { line: Some(line), message: format!("unknown shape `{}`", name) }),
+        };
+        if self.in_progress.iter().any(|n| n == name) {
+            return Err(error_at(self.source, desc, format!("shape `{}` refers back to itself", name)));
+        }
+
+        self.in_progress.push(name.to_string());
+        let shape = build_object(desc, self)?;
+        self.in_progress.pop();
+
+        self.built.insert(name.to_string(), shape.clone());
+        Ok(shape)
+    }
+
+    // Whether a shape has an inside, for CSG
+    fn is_solid(&self, desc: &ObjectDesc) -> bool {
+        match desc {
+            ObjectDesc::Sphere { radius, .. } => *radius > 0.0,
+            ObjectDesc::Box { .. } | ObjectDesc::Torus { .. } | ObjectDesc::Csg { .. } => true,
+            ObjectDesc::Cylinder { capped, phi_max, .. } | ObjectDesc::Cone { capped, phi_max, .. } => *capped && *phi_max >= 360.0,
+            // get has already caught any loops by the time this is asked
+            ObjectDesc::Instance { shape, .. } => self.descs.get(shape).is_some_and(|d| self.is_solid(d.get_ref())),
+            _ => false,
+        }
+    }
+}
+
+fn build_object(object: &Spanned<ObjectDesc>, shapes: &mut Shapes) -> Result<Arc<dyn Hittable>, SceneError> {
+    let (source, base_dir, materials) = (shapes.source, shapes.base_dir, shapes.materials);
+    let line = line_of(source, object.span().start);
+    let lookup = |name: &str| match materials.get(name) {
+        Some(mat) => Ok(mat.clone()),
+        None => Err(error_at(source, object, format!("unknown material `{}`", name))),
+    };
+
+    let hittable: Arc<dyn Hittable> = match object.get_ref() {
+        ObjectDesc::Sphere { center, center_end, radius, material } => {
+            if *radius == 0.0 {
+                return Err(error_at(source, object, "sphere radius cannot be zero".to_string()));
+            }
+            match center_end {
+                Some(center_end) => Arc::new(MovingSphere {
+                    center0: vec3(*center),
+                    center1: vec3(*center_end),
+                    time0: 0.0,
+                    time1: 1.0,
+                    radius: *radius,
+                    mat_ptr: lookup(material)?,
+                }),
+                None => Arc::new(Sphere { center: vec3(*center), radius: *radius, mat_ptr: lookup(material)? }),
+            }
+        }
+        ObjectDesc::Triangle { vertices, material } => {
+            let [a, b, c] = *vertices;
+            if (vec3(b) - vec3(a)).cross(vec3(c) - vec3(a)).len_sqred() == 0.0 {
+                return Err(error_at(source, object, "triangle has no area".to_string()));
+            }
+            Arc::new(Planar::triangle(vec3(a), vec3(b), vec3(c), lookup(material)?))
+        }
+        ObjectDesc::Quad { q, u, v, material } => {
+            if vec3(*u).cross(vec3(*v)).len_sqred() == 0.0 {
+                return Err(error_at(source, object, "quad edges u and v must not be parallel".to_string()));
+            }
+            Arc::new(Planar::quad(vec3(*q), vec3(*u), vec3(*v), lookup(material)?))
+        }
+        ObjectDesc::Rect { plane, min, max, k, material } => {
+            if min[0] == max[0] || min[1] == max[1] {
+                return Err(error_at(source, object, "rect has no area".to_string()));
+            }
+            let mat = lookup(material)?;
+            let ([a0, b0], [a1, b1]) = (*min, *max);
+            Arc::new(match plane.as_str() {
+                "xy" => Planar::xy_rect(a0, a1, b0, b1, *k, mat),
+                "xz" => Planar::xz_rect(a0, a1, b0, b1, *k, mat),
+                "yz" => Planar::yz_rect(a0, a1, b0, b1, *k, mat),
+                _ => return Err(error_at(source, object, format!("unknown plane `{}`, expected xy, xz or yz", plane))),
+            })
+        }
+        ObjectDesc::Disk { center, normal, radius, material } => {
+            if *radius <= 0.0 || vec3(*normal).len_sqred() == 0.0 {
+                return Err(error_at(source, object, "disk needs a positive radius and a non zero normal".to_string()));
+            }
+            Arc::new(Planar::disk(vec3(*center), vec3(*normal), *radius, lookup(material)?))
+        }
+        ObjectDesc::Box { min, max, material } => {
+            if (0..3).any(|i| min[i] >= max[i]) {
+                return Err(error_at(source, object, "box min must be below max on every axis".to_string()));
+            }
+            Arc::new(BoxShape::new(vec3(*min), vec3(*max), lookup(material)?))
+        }
+        ObjectDesc::Cylinder { base, axis, radius, height, capped, phi_max, material } => {
+            if *radius <= 0.0 || *height <= 0.0 || *phi_max <= 0.0 {
+                return Err(error_at(source, object, "cylinder radius, height and phi_max must be positive".to_string()));
+            }
+            let cylinder = Cylinder { radius: *radius, height: *height, capped: *capped, phi_max: *phi_max, mat_ptr: lookup(material)? };
+            place_on_axis(source, object, Arc::new(cylinder), *base, *axis)?
+        }
+        ObjectDesc::Cone { base, axis, radius, height, top, capped, phi_max, material } => {
+            let top = top.unwrap_or(*height);
+            if *radius <= 0.0 || *height <= 0.0 || *phi_max <= 0.0 || top <= 0.0 || top > *height {
+                return Err(error_at(source, object, "cone radius, height and phi_max must be positive, and top no higher than height".to_string()));
+            }
+            let cone = Cone { radius: *radius, height: *height, top, capped: *capped, phi_max: *phi_max, mat_ptr: lookup(material)? };
+            place_on_axis(source, object, Arc::new(cone), *base, *axis)?
+        }
+        ObjectDesc::Torus { center, axis, major_radius, minor_radius, material } => {
+            if *minor_radius <= 0.0 || *major_radius <= 0.0 {
+                return Err(error_at(source, object, "torus radii must be positive".to_string()));
+            }
+            let torus = Torus { major_radius: *major_radius, minor_radius: *minor_radius, mat_ptr: lookup(material)? };
+            place_on_axis(source, object, Arc::new(torus), *center, *axis)?
+        }
+        ObjectDesc::Quadric { coefficients, min, max, material } => {
+            if coefficients.iter().all(|c| *c == 0.0) {
+                return Err(error_at(source, object, "quadric coefficients cannot all be zero".to_string()));
+            }
+            if (0..3).any(|i| min[i] >= max[i]) {
+                return Err(error_at(source, object, "quadric clip box min must be below max on every axis".to_string()));
+            }
+            Arc::new(Quadric { coeffs: *coefficients, clip: Aabb::new(vec3(*min), vec3(*max)), mat_ptr: lookup(material)? })
+        }
+        ObjectDesc::Mesh { vertices, faces, normals, uvs, material } => {
+            let per_vertex = |len: usize, what: &str| {
+                if len == vertices.len() {
+                    Ok(())
+                } else {
+                    Err(error_at(source, object, format!("mesh has {} vertices but {} {}", vertices.len(), len, what)))
+                }
+            };
+            if let Some(normals) = normals {
+                per_vertex(normals.len(), "normals")?;
+            }
+            if let Some(uvs) = uvs {
+                per_vertex(uvs.len(), "uvs")?;
+            }
+
+            let faces = faces
+                .iter()
+                .map(|&f| MeshFace {
+                    vertices: f,
+                    normals: normals.as_ref().map(|_| f),
+                    uvs: uvs.as_ref().map(|_| f),
+                })
+                .collect();
+            let mesh = TriangleMesh::new(
+                vertices.iter().map(|&v| vec3(v)).collect(),
+                normals.iter().flatten().map(|&n| vec3(n)).collect(),
+                uvs.iter().flatten().map(|uv| (uv[0], uv[1])).collect(),
+                faces,
+                lookup(material)?,
+            )
+            .map_err(|message| error_at(source, object, message))?;
+            Arc::new(mesh.into_bvh())
+        }
+        ObjectDesc::Obj { path, material } => {
+            let material = match material {
+                Some(name) => lookup(name)?,
+                None => Arc::new(Lambertian::new(Colour { x: 0.8, y: 0.8, z: 0.8 })),
+            };
+            let groups = load_obj(&base_dir.join(path), material).map_err(|message| error_at(source, object, message))?;
+            Arc::new(into_hittable_list(groups).into_bvh())
+        }
+        ObjectDesc::Ply { path, material } => {
+            let mesh = load_ply(&base_dir.join(path), lookup(material)?).map_err(|message| error_at(source, object, message))?;
+            Arc::new(mesh.into_bvh())
+        }
+        ObjectDesc::Stl { path, material } => {
+            let mesh = load_stl(&base_dir.join(path), lookup(material)?).map_err(|message| error_at(source, object, message))?;
+            Arc::new(mesh.into_bvh())
+        }
+        ObjectDesc::Instance { shape, scale, rotate, translate, scale_end, rotate_end, translate_end } => {
+            let shape = shapes.get(shape, line)?;
+            let scale_of = |scale: &ScaleDesc| match scale {
+                ScaleDesc::Uniform(s) => Vec3 { x: *s, y: *s, z: *s },
+                ScaleDesc::PerAxis(s) => vec3(*s),
+            };
+            let start = Placement {
+                scale: scale.as_ref().map_or(Placement::identity().scale, scale_of),
+                rotate: rotate.map_or(Vec3::new(), vec3),
+                translate: translate.map_or(Vec3::new(), vec3),
+            };
+            // anything without an end stays as it starts
+            let end = Placement {
+                scale: scale_end.as_ref().map_or(start.scale, scale_of),
+                rotate: rotate_end.map_or(start.rotate, vec3),
+                translate: translate_end.map_or(start.translate, vec3),
+            };
+
+            if start == end {
+                Arc::new(Instance::new(shape, start.matrix()).map_err(|message| error_at(source, object, message))?)
+            } else {
+                Arc::new(MovingInstance::new(shape, start, end, 0.0, 1.0).map_err(|message| error_at(source, object, message))?)
+            }
+        }
+        ObjectDesc::Medium { boundary, density, material } => {
+            if *density <= 0.0 {
+                return Err(error_at(source, object, format!("medium density must be positive, got {}", density)));
+            }
+            Arc::new(ConstantMedium::new(shapes.get(boundary, line)?, *density, lookup(material)?))
+        }
+        ObjectDesc::Sdf { sdf, min, max, material } => {
+            let sdf = build_sdf(sdf).map_err(|message| error_at(source, object, message))?;
+            let bbox = match (min, max, sdf.bounds()) {
+                (Some(min), Some(max), _) => Aabb::new(vec3(*min), vec3(*max)),
+                (None, None, Some(bounds)) => bounds,
+                (None, None, None) => return Err(error_at(source, object, "this sdf goes on forever, give it a `min` and `max` to trace within".to_string())),
+                _ => return Err(error_at(source, object, "sdf needs both `min` and `max`, or neither".to_string())),
+            };
+            Arc::new(SdfObject::new(sdf, bbox, lookup(material)?))
+        }
+        ObjectDesc::Heightfield { min, size, image, noise, material } => {
+            let grid = match (image, noise) {
+                (Some(path), None) => HeightGrid::from_image(&read_image(&base_dir.join(path)).map_err(|message| error_at(source, object, message))?),
+                (None, Some(noise)) => {
+                    let [nx, nz] = noise.resolution;
+                    HeightGrid::from_noise(&Perlin::new(noise.seed), noise.scale, noise.octaves, nx, nz)
+                }
+                _ => return Err(error_at(source, object, "heightfield needs one of `image` or `noise`".to_string())),
+            };
+            let field = Heightfield::new(grid, vec3(*min), vec3(*size), lookup(material)?).map_err(|message| error_at(source, object, message))?;
+            Arc::new(field)
+        }
+        ObjectDesc::Volume { min, max, density, grid, noise, material } => {
+            let bbox = Aabb::new(vec3(*min), vec3(*max));
+            let size = bbox.maximum - bbox.minimum;
+            if size.x <= 0.0 || size.y <= 0.0 || size.z <= 0.0 {
+                return Err(error_at(source, object, "volume box has no size".to_string()));
+            }
+            if *density < 0.0 {
+                return Err(error_at(source, object, format!("volume density cannot be negative, got {}", density)));
+            }
+            let field: Arc<dyn DensityField> = match (grid, noise) {
+                (Some(path), None) => Arc::new(load_voxel_grid(&base_dir.join(path)).map_err(|message| error_at(source, object, message))?),
+                (None, Some(noise)) => Arc::new(NoiseDensity {
+                    noise: Perlin::new(noise.seed),
+                    scale: noise.scale,
+                    octaves: noise.octaves,
+                    bias: noise.bias,
+                    falloff: noise.falloff,
+                }),
+                _ => return Err(error_at(source, object, "volume needs one of `grid` or `noise`".to_string())),
+            };
+            Arc::new(HeterogeneousMedium { bbox, field, density: *density, phase_function: lookup(material)? })
+        }
+        ObjectDesc::Csg { operation, left, right } => {
+            let op = match operation.as_str() {
+                "union" => CsgOp::Union,
+                "intersection" => CsgOp::Intersection,
+                "difference" => CsgOp::Difference,
+                _ => return Err(error_at(source, object, format!("unknown operation `{}`, expected union, intersection or difference", operation))),
+            };
+            let (left_shape, right_shape) = (shapes.get(left, line)?, shapes.get(right, line)?);
+            for name in [left, right].iter() {
+                if !shapes.is_solid(shapes.descs[name.as_str()].get_ref()) {
+                    return Err(error_at(source, object, format!("shape `{}` is not a closed solid, so cannot be used in csg", name)));
+                }
+            }
+            Arc::new(Csg::new(op, left_shape, right_shape))
+        }
+    };
+    Ok(hittable)
+}
+
+
+
+// TEST
+#[test]
+fn test_parse_scene() {
+    let source = r#"
+[camera]
+lookfrom = [13, 2, 3]
+lookat = [0, 0, 0]
+vfov = 20
+
+[textures.board]
+type = "checker"
+even = [0.2, 0.3, 0.1]
+odd = "white"
+scale = 0.5
+
+[textures.white]
+type = "solid"
+colour = [0.9, 0.9, 0.9]
+
+[materials.ground]
+type = "lambertian"
+albedo = "board"
+
+[materials.glass]
+type = "dielectric"
+ir = 1.5
+
+[[objects]]
+type = "sphere"
+center = [0, -1000, 0]
+radius = 1000
+material = "ground"
+
+[[objects]]
+type = "sphere"
+center = [0, 1, 0]
+center_end = [0, 1.5, 0]
+radius = 1
+material = "glass"
+
+[[objects]]
+type = "mesh"
+vertices = [[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0]]
+faces = [[0, 1, 2], [0, 2, 3]]
+uvs = [[0, 0], [1, 0], [1, 1], [0, 1]]
+material = "ground"
+
+[shapes.ball]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "glass"
+
+[[objects]]
+type = "instance"
+shape = "ball"
+scale = [1, 0.5, 1]
+rotate = [0, 0, 30]
+translate = [3, 1, 0]
+
+[shapes.cube]
+type = "box"
+min = [-1, -1, -1]
+max = [1, 1, 1]
+material = "ground"
+
+[shapes.carved]
+type = "csg"
+operation = "difference"
+left = "cube"
+right = "ball"
+
+[[objects]]
+type = "instance"
+shape = "carved"
+rotate = [0, 45, 0]
+translate = [-3, 1, 0]
+rotate_end = [0, 90, 0]
+
+[materials.mist]
+type = "isotropic"
+albedo = "white"
+
+[[objects]]
+type = "medium"
+boundary = "ball"
+density = 0.5
+material = "mist"
+
+[[objects]]
+type = "volume"
+min = [-1, 0, -1]
+max = [1, 2, 1]
+noise = { seed = 1, falloff = 1 }
+material = "mist"
+
+[[objects]]
+type = "sdf"
+sdf = { type = "union", smooth = 0.2, a = { type = "torus", major_radius = 1, minor_radius = 0.25 }, b = { type = "mandelbulb" } }
+material = "ground"
+
+[[objects]]
+type = "heightfield"
+min = [-10, -1, -10]
+size = [20, 1, 20]
+noise = { resolution = [16, 16] }
+material = "ground"
+
+[[lights]]
+type = "point"
+position = [0, 5, 0]
+intensity = [10, 10, 10]
+
+[[lights]]
+type = "directional"
+direction = [1, -1, 0]
+intensity = [2, 2, 2]
+"#;
+    let scene = parse_scene(source, Path::new("."), 1.5).unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(scene.camera.origin, Point3 { x: 13.0, y: 2.0, z: 3.0 });
+    assert_eq!(scene.background, Background::Gradient);
+    assert!(scene.world.bounding_box().is_some());
+    assert_eq!(scene.delta_lights.len(), 2);
+}
+
+#[test]
+fn test_scene_errors_have_line_numbers() {
+    let camera = "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\nvfov = 90\n";
+
+    let unknown_material = format!("{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"nope\"\n", camera);
+    let err = parse_scene(&unknown_material, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("nope"));
+    assert!(matches!(err.line, Some(6..=10)), "{:?}", err);
+
+    let bad_type = format!("{}\n[materials.m]\ntype = \"plastic\"\n", camera);
+    let err = parse_scene(&bad_type, Path::new("."), 1.0).err().unwrap();
+    assert!(matches!(err.line, Some(6..=7)), "{:?}", err);
+
+    let bad_background = format!("background = \"purple\"\n{}", camera);
+    let err = parse_scene(&bad_background, Path::new("."), 1.0).err().unwrap();
+    assert_eq!(err.line, Some(1));
+
+    let missing_environment = format!("background = {{ image = \"missing.hdr\", rotation = 90 }}\n{}", camera);
+    let err = parse_scene(&missing_environment, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("missing.hdr"), "{:?}", err);
+    assert_eq!(err.line, Some(1));
+
+    let texture_loop = format!("{}\n[textures.a]\ntype = \"checker\"\neven = \"b\"\nodd = [1, 1, 1]\n\n[textures.b]\ntype = \"checker\"\neven = \"a\"\nodd = [0, 0, 0]\n\n[materials.m]\ntype = \"lambertian\"\nalbedo = \"a\"\n", camera);
+    let err = parse_scene(&texture_loop, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("refers back to itself"), "{:?}", err);
+
+    let unknown_shape = format!("{}\n[[objects]]\ntype = \"instance\"\nshape = \"nope\"\n", camera);
+    let err = parse_scene(&unknown_shape, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("unknown shape"), "{:?}", err);
+
+    let open_csg = format!("{}\n[shapes.a]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"m\"\n\n[shapes.b]\ntype = \"disk\"\ncenter = [0, 0, 0]\nnormal = [0, 1, 0]\nradius = 1\nmaterial = \"m\"\n\n[materials.m]\ntype = \"lambertian\"\nalbedo = [1, 1, 1]\n\n[[objects]]\ntype = \"csg\"\noperation = \"union\"\nleft = \"a\"\nright = \"b\"\n", camera);
+    let err = parse_scene(&open_csg, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("not a closed solid"), "{:?}", err);
+
+    let endless = format!("{}\n[materials.m]\ntype = \"lambertian\"\nalbedo = [1, 1, 1]\n\n[[objects]]\ntype = \"sdf\"\nsdf = {{ type = \"repeat\", period = [2, 0, 2], shape = {{ type = \"sphere\", radius = 0.5 }} }}\nmaterial = \"m\"\n", camera);
+    let err = parse_scene(&endless, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("goes on forever"), "{:?}", err);
+
+    let wide_falloff = format!("{}\n[[lights]]\ntype = \"spot\"\nposition = [0, 1, 0]\ndirection = [0, -1, 0]\nintensity = [1, 1, 1]\nangle = 20\nfalloff = 30\n", camera);
+    let err = parse_scene(&wide_falloff, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("falloff"), "{:?}", err);
+    assert!(matches!(err.line, Some(6..=12)), "{:?}", err);
+
+    let flat_box = format!("{}\n[materials.m]\ntype = \"lambertian\"\nalbedo = [1, 1, 1]\n\n[[objects]]\ntype = \"box\"\nmin = [0, 0, 0]\nmax = [1, 0, 1]\nmaterial = \"m\"\n", camera);
+    let err = parse_scene(&flat_box, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("box"), "{:?}", err);
+    assert!(matches!(err.line, Some(10..=14)), "{:?}", err);
+
+    let quadric = |coefficients: &str, max: &str| format!("{}\n[materials.m]\ntype = \"lambertian\"\nalbedo = [1, 1, 1]\n\n[[objects]]\ntype = \"quadric\"\ncoefficients = {}\nmin = [-1, -1, -1]\nmax = {}\nmaterial = \"m\"\n", camera, coefficients, max);
+    let empty_quadric = quadric("[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]", "[1, 1, 1]");
+    let err = parse_scene(&empty_quadric, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("coefficients"), "{:?}", err);
+    assert!(matches!(err.line, Some(10..=15)), "{:?}", err);
+    let flat_quadric = quadric("[1, 1, 1, 0, 0, 0, 0, 0, 0, -1]", "[1, -1, 1]");
+    let err = parse_scene(&flat_quadric, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("clip box"), "{:?}", err);
+    assert!(matches!(err.line, Some(10..=15)), "{:?}", err);
+
+    let bad_camera = "[materials.m]\ntype = \"lambertian\"\nalbedo = [1, 1, 1]\n\n[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 1]\nvfov = 90\n";
+    let err = parse_scene(bad_camera, Path::new("."), 1.0).err().unwrap();
+    assert!(err.message.contains("lookfrom and lookat"), "{:?}", err);
+    assert_eq!(err.line, Some(5));
+
+    let syntax = "[camera]\nlookfrom = [0, 0, 1\n";
+    let err = parse_scene(syntax, Path::new("."), 1.0).err().unwrap();
+    assert!(err.line.is_some());
+}
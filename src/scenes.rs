@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::hittable::*;
+use crate::sphere::*;
+use crate::planar::*;
+use crate::transform::*;
+use crate::material::*;
+use crate::camera::*;
+use crate::random::*;
+use crate::background::*;
+use crate::light::*;
+
+
+// A world to render, the camera to look at it through and what is behind it all.
+// lights holds the glowing objects from the world that can be sampled directly,
+// so every diffuse bounce can look straight at them rather than hope to hit them.
+// delta_lights are points and suns, which are not in the world at all
+pub struct Scene {
+    pub world: Arc<HittableList>,
+    pub lights: Arc<HittableList>,
+    pub delta_lights: Vec<Arc<dyn Light>>,
+    pub camera: Camera,
+    pub background: Background,
+}
+
+
+pub fn random_scene(aspect_ratio: f64) -> Scene {
+
+    let mut world: HittableList = HittableList::new();
+    let mat_ground = Arc::new(Lambertian::new(Colour{x:0.5,y:0.5,z:0.5}));
+    world.add(Arc::new(Sphere{center: Point3{x: 0.0,y:-1000.0,z:0.0},radius: 1000.0, mat_ptr:mat_ground}));
+
+    for x in -11..11{
+        for z in -11..11{
+            let choose_mat = random_double();
+            let center = Point3{ x : x as f64 + 0.9*random_double()
+                                    , y : 0.2
+                                    , z : z as f64 + 0.9*random_double()
+                                    };
+
+
+            if (center - Point3{x:4.0, y:0.2, z:0.0}).len() > 0.9 {
+
+                if choose_mat < 0.6 {
+                    // diffuse
+                    let albedo = Colour::random() * Colour::random();
+                    let material = Arc::new(Lambertian::new(albedo));
+                    world.add(Arc::new(Sphere{ center, radius: 0.2, mat_ptr:material}));
+
+                } else if choose_mat < 0.8 {
+                    // metal
+                    let albedo = Colour::random() * Colour::random();
+                    let fuzz = random_range(0.0, 0.2);
+                    let material =  Arc::new(Metal::new(albedo, fuzz));
+                    world.add(Arc::new(Sphere{ center, radius: 0.2, mat_ptr:material}));
+                } else {
+                    // glass
+                    let material   = Arc::new(Dielectric{ ir: 1.5 });
+                    world.add(Arc::new(Sphere{ center, radius: 0.2, mat_ptr:material}));
+                }
+            }
+            // auto choose_mat = random_double();
+            // point3 center(a + 0.9*random_double(), 0.2, b + 0.9*random_double());
+
+        }
+    }
+    // world.add(Arc::new((point3(0, 1, 0), 1.0, material1));
+    let material1   = Arc::new(Dielectric{ ir: 1.5 });
+    world.add(Arc::new(Sphere{ center: Point3{x:0.0,y:1.0,z:0.0}, radius: 1.0, mat_ptr:material1}));
+
+    let material2 = Arc::new(Lambertian::new(Colour{x:0.4,y:0.2,z:0.1}));
+    world.add(Arc::new(Sphere{ center: Point3{x:-4.0,y:1.0,z:0.0}, radius: 1.0, mat_ptr:material2}));
+
+
+    let material3 = Arc::new(Metal::new(Colour{x:0.7,y:0.6,z:0.5}, 0.0));
+    world.add(Arc::new(Sphere{ center: Point3{x:4.0,y:1.0,z:0.0}, radius: 1.0, mat_ptr:material3}));
+
+    // auto material3 = make_shared<metal>(color(0.7, 0.6, 0.5), 0.0);
+    // world.add(make_shared<sphere>(point3(4, 1, 0), 1.0, material3));
+
+    // Camera
+    let lookfrom = Point3 { x:13.0, y:2.0, z: 2.0};
+    let lookat   = Point3 { x:0.0,  y:0.0, z: 0.0};
+    let vup      = Point3 { x:0.0,  y:1.0, z: 0.0};
+    let dist_to_focus =  10.0;
+    let aperture  =  0.1;
+
+    Scene {
+        world: Arc::new(world.into_bvh()),
+        lights: Arc::new(HittableList::new()),
+        delta_lights: Vec::new(),
+        camera: Camera::new(lookfrom,lookat,vup, 20.0,aspect_ratio,aperture,dist_to_focus),
+        background: Background::Gradient,
+    }
+}
+
+
+pub fn simple_scene(aspect_ratio: f64) -> Scene {
+
+    // Materials
+    let mat_ground = Arc::new(Lambertian::new(Colour{x:0.8,y:0.8,z:0.0}));
+    let mat_center = Arc::new(Lambertian::new(Colour{x:0.1,y:0.2,z:0.5}));
+    let mat_left   = Arc::new(Dielectric{ ir: 1.5 });
+    let mat_right =  Arc::new(Metal::new(Colour{x:0.8,y:0.6,z:0.2}, 0.0));
+
+    // World
+    let mut world: HittableList = HittableList::new();
+    world.add(Arc::new(Sphere{center: Point3{x: 0.0,y:-100.5,z:-1.0},radius: 100.0, mat_ptr:mat_ground}));
+    world.add(Arc::new(Sphere{center: Point3{x: 0.0,y:0.0,z:-1.0}   ,radius: 0.5,   mat_ptr:mat_center}));
+    world.add(Arc::new(Sphere{center: Point3{x:-1.0,y:0.0,z:-1.0}   ,radius: 0.5,   mat_ptr:mat_left.clone()}));
+    world.add(Arc::new(Sphere{center: Point3{x:-1.0,y:0.0,z:-1.0}   ,radius: -0.45, mat_ptr:mat_left}));
+    world.add(Arc::new(Sphere{center: Point3{x: 1.0,y:0.0,z:-1.0}   ,radius: 0.5,   mat_ptr:mat_right}));
+
+    // Camera
+    let lookfrom = Point3 { x:-2.0, y:2.0, z: 1.0};
+    let lookat   = Point3 { x:0.0,  y:0.0, z:-1.0};
+    let vup      = Point3 { x:0.0,  y:1.0, z: 0.0};
+    let dist_to_focus = (lookfrom - lookat).len();
+
+    Scene {
+        world: Arc::new(world),
+        lights: Arc::new(HittableList::new()),
+        delta_lights: Vec::new(),
+        camera: Camera::new(lookfrom,lookat,vup, 20.0,aspect_ratio,0.0,dist_to_focus),
+        background: Background::Gradient,
+    }
+}
+
+
+pub fn simple_light_scene(aspect_ratio: f64) -> Scene {
+
+    let mat_ground = Arc::new(Lambertian::new(Colour{x:0.5,y:0.5,z:0.5}));
+    let mat_sphere = Arc::new(Lambertian::new(Colour{x:0.8,y:0.3,z:0.2}));
+    let mat_light  = Arc::new(DiffuseLight{ emit:Colour{x:4.0,y:4.0,z:4.0} });
+
+    let mut world: HittableList = HittableList::new();
+    world.add(Arc::new(Sphere{center: Point3{x: 0.0,y:-1000.0,z:0.0},radius: 1000.0, mat_ptr:mat_ground}));
+    world.add(Arc::new(Sphere{center: Point3{x: 0.0,y:2.0,z:0.0}    ,radius: 2.0,    mat_ptr:mat_sphere}));
+    let light = Arc::new(Sphere{center: Point3{x: 0.0,y:7.0,z:0.0}    ,radius: 2.0,    mat_ptr:mat_light});
+    world.add(Arc::new(SampledLight { object: light.clone() }));
+
+    let mut lights = HittableList::new();
+    lights.add(light);
+
+    // Camera
+    let lookfrom = Point3 { x:26.0, y:3.0, z: 6.0};
+    let lookat   = Point3 { x:0.0,  y:2.0, z: 0.0};
+    let vup      = Point3 { x:0.0,  y:1.0, z: 0.0};
+    let dist_to_focus = (lookfrom - lookat).len();
+
+    Scene {
+        world: Arc::new(world.into_bvh()),
+        lights: Arc::new(lights),
+        delta_lights: Vec::new(),
+        camera: Camera::new(lookfrom,lookat,vup, 20.0,aspect_ratio,0.0,dist_to_focus),
+        background: Background::Solid(Colour::new()),
+    }
+}
+
+
+pub fn cornell_box_scene(aspect_ratio: f64) -> Scene {
+
+    let red   = Arc::new(Lambertian::new(Colour{x:0.65,y:0.05,z:0.05}));
+    let white = Arc::new(Lambertian::new(Colour{x:0.73,y:0.73,z:0.73}));
+    let green = Arc::new(Lambertian::new(Colour{x:0.12,y:0.45,z:0.15}));
+    let light = Arc::new(DiffuseLight{ emit:Colour{x:15.0,y:15.0,z:15.0} });
+
+    let mut world: HittableList = HittableList::new();
+    world.add(Arc::new(Planar::yz_rect(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    world.add(Arc::new(Planar::yz_rect(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    let light = Arc::new(Planar::xz_rect(213.0, 343.0, 227.0, 332.0, 554.0, light));
+    world.add(light.clone());
+    world.add(Arc::new(Planar::xz_rect(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())));
+    world.add(Arc::new(Planar::xz_rect(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
+    world.add(Arc::new(Planar::xy_rect(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
+
+    // the blocks are built at the origin, then turned and moved into place
+    let tall = Arc::new(BoxShape::new(Point3::new(), Point3{x:165.0,y:330.0,z:165.0}, white.clone()));
+    let tall_place = Mat4::translate(Vec3{x:265.0,y:0.0,z:295.0}) * Mat4::rotate(Vec3{x:0.0,y:1.0,z:0.0}, 15.0);
+    world.add(Arc::new(Instance::new(tall, tall_place).unwrap()));
+
+    let short = Arc::new(BoxShape::new(Point3::new(), Point3{x:165.0,y:165.0,z:165.0}, white));
+    let short_place = Mat4::translate(Vec3{x:130.0,y:0.0,z:65.0}) * Mat4::rotate(Vec3{x:0.0,y:1.0,z:0.0}, -18.0);
+    world.add(Arc::new(Instance::new(short, short_place).unwrap()));
+
+    let mut lights = HittableList::new();
+    lights.add(light);
+
+    // Camera
+    let lookfrom = Point3 { x:278.0, y:278.0, z:-800.0};
+    let lookat   = Point3 { x:278.0, y:278.0, z: 0.0};
+    let vup      = Point3 { x:0.0,   y:1.0,   z: 0.0};
+    let dist_to_focus = (lookfrom - lookat).len();
+
+    Scene {
+        world: Arc::new(world.into_bvh()),
+        lights: Arc::new(lights),
+        delta_lights: Vec::new(),
+        camera: Camera::new(lookfrom,lookat,vup, 40.0,aspect_ratio,0.0,dist_to_focus),
+        background: Background::Solid(Colour::new()),
+    }
+}
@@ -0,0 +1,367 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::*;
+use crate::sphere::{get_sphere_uv, set_face_normal};
+use std::sync::Arc;
+
+
+// A signed distance field: how far p is from the surface, negative inside.
+// It only has to be a lower bound on the true distance, tracing steps that far at a time.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: Point3) -> f64;
+
+    // None for fields that go on forever, such as repetitions
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+// Any function of the point is a distance field, for one-off shapes written in code
+impl<F: Fn(Point3) -> f64 + Send + Sync> Sdf for F {
+    fn distance(&self, p: Point3) -> f64 {
+        self(p)
+    }
+}
+
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3 { x: v.x.abs(), y: v.y.abs(), z: v.z.abs() }
+}
+
+fn max0(v: Vec3) -> Vec3 {
+    Vec3 { x: v.x.max(0.0), y: v.y.max(0.0), z: v.z.max(0.0) }
+}
+
+fn cube(center: Point3, half_size: Vec3) -> Aabb {
+    Aabb::new(center - half_size, center + half_size)
+}
+
+fn grow(b: Aabb, by: f64) -> Aabb {
+    let by = Vec3 { x: by, y: by, z: by };
+    Aabb::new(b.minimum - by, b.maximum + by)
+}
+
+
+pub struct SdfSphere {
+    pub center: Point3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Point3) -> f64 {
+        (p - self.center).len() - self.radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(cube(self.center, Vec3 { x: self.radius, y: self.radius, z: self.radius }))
+    }
+}
+
+// A box with its edges rounded off by radius, which is 0 for a sharp box.
+// The rounding is inside half_size, so the box keeps its size
+pub struct SdfBox {
+    pub center: Point3,
+    pub half_size: Vec3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Point3) -> f64 {
+        let r = Vec3 { x: self.radius, y: self.radius, z: self.radius };
+        let q = abs(p - self.center) - self.half_size + r;
+        max0(q).len() + q.x.max(q.y).max(q.z).min(0.0) - self.radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(cube(self.center, self.half_size))
+    }
+}
+
+// Lying flat in the xz plane
+pub struct SdfTorus {
+    pub center: Point3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Point3) -> f64 {
+        let p = p - self.center;
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        (ring * ring + p.y * p.y).sqrt() - self.minor_radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let (r, h) = (self.major_radius + self.minor_radius, self.minor_radius);
+        Some(cube(self.center, Vec3 { x: r, y: h, z: r }))
+    }
+}
+
+
+// Polynomial smooth minimum, blending over a distance of k. k = 0 is a plain min
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + h * (a - b) - k * h * (1.0 - h)
+}
+
+// a and b melted together where they meet
+pub struct SmoothUnion {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Point3) -> f64 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    // the blend can bulge out by at most k / 4
+    fn bounds(&self) -> Option<Aabb> {
+        let (a, b) = (self.a.bounds()?, self.b.bounds()?);
+        Some(grow(Aabb::surrounding_box(a, b), self.k.max(0.0) / 4.0))
+    }
+}
+
+// a with b carved out of it, with a rounded groove where they meet
+pub struct SmoothSubtraction {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothSubtraction {
+    fn distance(&self, p: Point3) -> f64 {
+        -smooth_min(-self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.a.bounds()
+    }
+}
+
+// Endless copies of a shape every period along each axis, with the one at the
+// origin repeated. An axis with a period of 0 is not repeated
+pub struct Repeat {
+    pub shape: Arc<dyn Sdf>,
+    pub period: Vec3,
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: Point3) -> f64 {
+        let wrap = |x: f64, period: f64| if period > 0.0 { x - period * (x / period).round() } else { x };
+        self.shape.distance(Point3 { x: wrap(p.x, self.period.x), y: wrap(p.y, self.period.y), z: wrap(p.z, self.period.z) })
+    }
+}
+
+// Twists a shape about the y axis by rate radians per unit of height
+pub struct Twist {
+    pub shape: Arc<dyn Sdf>,
+    pub rate: f64,
+    // twisting stretches distances, so steps are shortened to stay safe
+    lipschitz: f64,
+}
+
+impl Twist {
+    pub fn new(shape: Arc<dyn Sdf>, rate: f64) -> Twist {
+        // a point r from the axis is moved sideways by up to rate * r per unit of height
+        let reach = shape.bounds().map_or(1.0, |b| {
+            let x = b.minimum.x.abs().max(b.maximum.x.abs());
+            let z = b.minimum.z.abs().max(b.maximum.z.abs());
+            (x * x + z * z).sqrt()
+        });
+        let lipschitz = (1.0 + (rate * reach).powi(2)).sqrt();
+        Twist { shape, rate, lipschitz }
+    }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: Point3) -> f64 {
+        let (sin, cos) = (self.rate * p.y).sin_cos();
+        let q = Point3 { x: cos * p.x - sin * p.z, y: p.y, z: sin * p.x + cos * p.z };
+        self.shape.distance(q) / self.lipschitz
+    }
+
+    // anything the shape reaches at any angle about the axis
+    fn bounds(&self) -> Option<Aabb> {
+        let b = self.shape.bounds()?;
+        let x = b.minimum.x.abs().max(b.maximum.x.abs());
+        let z = b.minimum.z.abs().max(b.maximum.z.abs());
+        let r = (x * x + z * z).sqrt();
+        Some(Aabb::new(Point3 { x: -r, y: b.minimum.y, z: -r }, Point3 { x: r, y: b.maximum.y, z: r }))
+    }
+}
+
+// The power 8 Mandelbulb fractal (or any other power), about the size of a unit sphere
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: u32,
+}
+
+impl Sdf for Mandelbulb {
+    // The usual distance estimate from the running derivative of the iteration
+    fn distance(&self, p: Point3) -> f64 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.len();
+        for _ in 0..self.iterations {
+            if !(1e-12..=2.0).contains(&r) {
+                break;
+            }
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = zr * Vec3 { x: theta.sin() * phi.cos(), y: phi.sin() * theta.sin(), z: theta.cos() } + p;
+            r = z.len();
+        }
+        if r < 1e-12 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(cube(Point3::new(), Vec3 { x: 1.2, y: 1.2, z: 1.2 }))
+    }
+}
+
+
+// A distance field shape in the scene, found by sphere tracing: step along the ray by the
+// distance to the nearest surface until that distance is tiny. Only the part of the field
+// inside bbox is traced. Normals come from the field's gradient, estimated numerically.
+pub struct SdfObject {
+    pub sdf: Arc<dyn Sdf>,
+    pub bbox: Aabb,
+    pub mat_ptr: Arc<dyn Material>,
+    // how close counts as on the surface
+    pub epsilon: f64,
+    pub max_steps: u32,
+}
+
+impl SdfObject {
+
+    pub fn new(sdf: Arc<dyn Sdf>, bbox: Aabb, mat_ptr: Arc<dyn Material>) -> SdfObject {
+        SdfObject { sdf, bbox, mat_ptr, epsilon: 1e-4, max_steps: 512 }
+    }
+
+    // Central differences at the four corners of a tetrahedron, which needs four lookups instead of six
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = self.epsilon;
+        let corners = [
+            Vec3 { x: 1.0, y: -1.0, z: -1.0 },
+            Vec3 { x: -1.0, y: -1.0, z: 1.0 },
+            Vec3 { x: -1.0, y: 1.0, z: -1.0 },
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 },
+        ];
+        let gradient = corners.iter().fold(Vec3::new(), |acc, k| acc + self.sdf.distance(p + h * *k) * *k);
+        if gradient.len_sqred() > 0.0 { gradient.unit_vector() } else { Vec3 { x: 0.0, y: 1.0, z: 0.0 } }
+    }
+}
+
+impl Hittable for SdfObject {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let (start, end) = grow(self.bbox, self.epsilon).clip(ray, tmin, tmax)?;
+        let ray_length = ray.dir.len();
+
+        // A ray leaving the surface (say after a bounce) starts right on it, so a hit only
+        // counts once the ray has got clear. Until then the side it is on can still change.
+        // Rays coming in from outside the box cannot be leaving anything
+        let mut t = start;
+        let mut leaving = start <= tmin;
+        let mut inside = false;
+        let mut steps = 0;
+        loop {
+            let d = self.sdf.distance(ray.clone().at(t));
+            if leaving {
+                inside = d < 0.0;
+                leaving = d.abs() < 2.0 * self.epsilon;
+            } else if d.abs() < self.epsilon || (d < 0.0) != inside {
+                break;
+            }
+            t += d.abs().max(self.epsilon) / ray_length;
+            steps += 1;
+            // running out of steps only happens skimming past a surface, so count it as a miss
+            if t > end || steps == self.max_steps {
+                return None;
+            }
+        }
+
+        let p = ray.clone().at(t);
+        let (front_face, normal) = set_face_normal(ray, self.normal(p));
+        let (u, v) = get_sphere_uv(self.normal(p));
+        Some(HitRecord {
+            p,
+            normal,
+            mat_ptr: self.mat_ptr.clone(),
+            t,
+            u,
+            v,
+            front_face,
+            colour: None,
+            sampled_light: false,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_sdf_nodes() {
+    let ball = SdfSphere { center: Point3::new(), radius: 1.0 };
+    let block = SdfBox { center: Point3 { x: 1.0, y: 0.0, z: 0.0 }, half_size: Vec3 { x: 0.5, y: 0.5, z: 0.5 }, radius: 0.0 };
+    assert_eq!(ball.distance(Point3 { x: 0.0, y: 3.0, z: 0.0 }), 2.0);
+    assert_eq!(block.distance(Point3 { x: 1.0, y: 2.0, z: 0.0 }), 1.5);
+
+    // a hard union is the nearer of the two, the smooth one is never further
+    let union = SmoothUnion { a: Arc::new(ball), b: Arc::new(block), k: 0.0 };
+    let p = Point3 { x: 2.0, y: 0.0, z: 0.0 };
+    assert_eq!(union.distance(p), 0.5);
+    let smooth = SmoothUnion { k: 0.5, ..union };
+    assert!(smooth.distance(p) <= 0.5);
+    let bounds = smooth.bounds().unwrap();
+    assert_eq!(bounds.maximum.x, 1.5 + 0.125);
+
+    let repeated = Repeat { shape: Arc::new(SdfSphere { center: Point3::new(), radius: 1.0 }), period: Vec3 { x: 10.0, y: 0.0, z: 0.0 } };
+    assert!((repeated.distance(Point3 { x: 31.0, y: 0.0, z: 0.0 }) - 0.0).abs() < 1e-12);
+    assert!(repeated.bounds().is_none());
+
+    // points on the bulb's axis beyond it are outside, the centre is inside
+    let bulb = Mandelbulb { power: 8.0, iterations: 12 };
+    assert!(bulb.distance(Point3 { x: 0.0, y: 0.0, z: 1.5 }) > 0.0);
+    assert!(bulb.distance(Point3 { x: 0.1, y: 0.1, z: 0.1 }) < 1e-3);
+}
+
+#[test]
+fn test_sphere_tracing() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let ball: Arc<dyn Sdf> = Arc::new(SdfSphere { center: Point3::new(), radius: 1.0 });
+    let object = SdfObject::new(ball.clone(), ball.bounds().unwrap(), mat);
+
+    let ray = Ray::new(Point3 { x: 0.0, y: 0.0, z: 5.0 }, Vec3 { x: 0.0, y: 0.0, z: -2.0 });
+    let hit = object.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 2.0).abs() < 1e-3);
+    assert!((hit.normal - Vec3 { x: 0.0, y: 0.0, z: 1.0 }).len() < 1e-3);
+    assert!(hit.front_face);
+
+    // refracted into the ball from that hit, it comes out the far side
+    let inside = Ray::new(hit.p, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let exit = object.hit(&inside, 0.001, f64::INFINITY).unwrap();
+    assert!((exit.p.z + 1.0).abs() < 1e-3);
+    assert!(!exit.front_face);
+
+    // and reflected away from it, there is nothing else to hit
+    let away = Ray::new(hit.p, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(object.hit(&away, 0.001, f64::INFINITY).is_none());
+}
@@ -1,17 +1,19 @@
 use crate::vec3::*;
 use crate::ray::*;
 use crate::hittable::*;
-use crate::hittable::Hittable;
 use crate::material::Material;
+use crate::aabb::*;
+use crate::random::*;
 // use std::rc::Rc;
 use std::sync::Arc;
+use std::f64::consts::PI;
 
 
 
 pub struct Sphere  {
     pub center: Point3,
     pub radius: f64,
-    pub mat_ptr: Arc<Material>,
+    pub mat_ptr: Arc<dyn Material>,
 }
 
 type IsFrontFace = bool;
@@ -20,62 +22,214 @@ type Normal = Vec3;
 pub fn set_face_normal(ray:&Ray, outward_normal: Vec3) -> (IsFrontFace, Normal) {
     let front_face = ray.dir.dot(outward_normal) < 0.0;
     if front_face {
-        return ( front_face, outward_normal);
+        ( front_face, outward_normal)
     } else {
-        return ( front_face, -outward_normal);
+        ( front_face, -outward_normal)
     }
 }
 
 
+// p is a point on the unit sphere centred at the origin.
+// u: angle around the Y axis from X=-1, v: angle from Y=-1 to Y=+1, both scaled to [0,1]
+pub fn get_sphere_uv(p: Point3) -> (f64, f64) {
+    let theta = f64::acos(-p.y);
+    let phi = f64::atan2(-p.z, p.x) + PI;
+
+    (phi / (2.0 * PI), theta / PI)
+}
+
+
+// Both places the ray's line crosses the sphere, nearest first
+fn roots(center: Point3, radius: f64, ray: &Ray) -> Option<(f64, f64)> {
+    let oc:Vec3 = ray.orig - center;
+    let a = ray.dir.len_sqred();
+    let half_b = oc.dot(ray.dir);
+    let c = oc.len_sqred() - radius*radius;
+    let discriminant = half_b*half_b - a*c;
+
+    if discriminant > 0.0 {
+        let root = discriminant.sqrt();
+        Some(((-half_b - root)/a, (-half_b + root)/a))
+    } else {
+        None
+    }
+}
+
+fn record_at(center: Point3, radius: f64, mat_ptr: &Arc<dyn Material>, ray: &Ray, t: f64) -> HitRecord {
+    let temp_point = ray.clone().at(t);
+    let outward_normal: Vec3 = (temp_point - center) / radius;// unit norm
+    let (ff,norm)= set_face_normal(ray,outward_normal);
+    let (u,v) = get_sphere_uv((temp_point - center) / radius.abs());
+
+    HitRecord{
+        p: temp_point,
+        normal: norm,
+        mat_ptr: mat_ptr.clone(),
+        t,
+        u,
+        v,
+        front_face:ff,
+        colour: None,
+        sampled_light: false,
+    }
+}
+
+fn hit_sphere(center: Point3, radius: f64, mat_ptr: &Arc<dyn Material>, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+    let (near, far) = roots(center, radius, ray)?;
+    if near<tmax && near>tmin {
+        return Some(record_at(center, radius, mat_ptr, ray, near));
+    }
+    if far<tmax && far>tmin {
+        return Some(record_at(center, radius, mat_ptr, ray, far));
+    }
+    None
+}
+
+// A negative radius turns the sphere inside out, which has no finite inside
+fn sphere_intervals(center: Point3, radius: f64, mat_ptr: &Arc<dyn Material>, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+    if radius < 0.0 {
+        return None;
+    }
+    Some(match roots(center, radius, ray) {
+        Some((near, far)) => vec![(record_at(center, radius, mat_ptr, ray, near), record_at(center, radius, mat_ptr, ray, far))],
+        None => Vec::new(),
+    })
+}
+
+fn sphere_box(center: Point3, radius: f64) -> Aabb {
+    let r = Vec3 { x: radius.abs(), y: radius.abs(), z: radius.abs() };
+    Aabb::new(center - r, center + r)
+}
+
+
 impl Hittable for Sphere {
 
     fn hit(&self,ray:&Ray, tmin:f64, tmax:f64 ) -> Option<HitRecord>{
-        let oc:Vec3 = ray.orig - self.center;
-        let a = ray.dir.len_sqred();
-        let half_b = oc.dot(ray.dir);
-        let c = oc.len_sqred() - self.radius*self.radius;
-        let discriminant = half_b*half_b - a*c;
-
-        if discriminant > 0.0 {
-            let root = discriminant.sqrt();
-
-            let mut temp = (-half_b - root)/a;
-            if temp<tmax && temp>tmin{
-                // hitrecord
-                let temp_point = ray.clone().at(temp); 
-                let outward_normal: Vec3 = (temp_point - self.center) / self.radius;// unit norm
-                let (ff,norm)= set_face_normal(&ray,outward_normal);
-
-                let hr = HitRecord{ 
-                    p: ray.clone().at(temp), 
-                    normal: norm, 
-                    t: temp,
-                    mat_ptr: self.mat_ptr.clone(),
-                    front_face:ff
-                };
-                return Some(hr);
-            }
-
-            let mut temp = (-half_b + root)/a;
-            if temp<tmax && temp>tmin {
-                let temp_point = ray.clone().at(temp); 
-                let outward_normal: Vec3 = (temp_point - self.center) / self.radius;// unit norm
-                let (ff,norm)= set_face_normal(&ray,outward_normal);
-
-                let hr = HitRecord{ 
-                    p: ray.clone().at(temp), 
-                    normal: norm, 
-                    mat_ptr: self.mat_ptr.clone(),
-                    t: temp, 
-                    front_face:ff
-                };
-                return Some(hr);
-            } ;
+        hit_sphere(self.center, self.radius, &self.mat_ptr, ray, tmin, tmax)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        sphere_intervals(self.center, self.radius, &self.mat_ptr, ray)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.center, self.radius))
+    }
+
+    // Directions are picked evenly from the cone the sphere fills as seen from origin,
+    // or from all around when origin is inside it
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        if self.hit(&Ray::with_time(origin, direction, time), 0.001, f64::INFINITY).is_none() {
+            return 0.0;
         }
+        let distance_sqred = (self.center - origin).len_sqred();
+        let radius_sqred = self.radius * self.radius;
+        if distance_sqred <= radius_sqred {
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = (1.0 - radius_sqred / distance_sqred).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
 
-        return None;
+    fn random_direction(&self, origin: Point3) -> Option<Vec3> {
+        let to_center = self.center - origin;
+        let distance_sqred = to_center.len_sqred();
+        let radius_sqred = self.radius * self.radius;
+        if distance_sqred <= radius_sqred {
+            return Some(Vec3::random_in_unit_vector());
+        }
+
+        let cos_theta_max = (1.0 - radius_sqred / distance_sqred).sqrt();
+        let z = 1.0 + random_double() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * random_double();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Some(Onb::build_from_w(to_center).local(Vec3 { x: r * phi.cos(), y: r * phi.sin(), z }))
+    }
+
+}
+
+
+// A sphere moving in a straight line from center0 at time0 to center1 at time1,
+// staying put before and after
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let f = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + f * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        hit_sphere(self.center(ray.time), self.radius, &self.mat_ptr, ray, tmin, tmax)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        sphere_intervals(self.center(ray.time), self.radius, &self.mat_ptr, ray)
     }
 
+    // everywhere it goes
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::surrounding_box(sphere_box(self.center0, self.radius), sphere_box(self.center1, self.radius)))
+    }
 }
 
 
+
+// TEST
+#[test]
+fn test_moving_sphere() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let sphere = MovingSphere {
+        center0: Point3::new(),
+        center1: Point3 { x: 4.0, y: 0.0, z: 0.0 },
+        time0: 0.0,
+        time1: 1.0,
+        radius: 1.0,
+        mat_ptr: mat,
+    };
+    let down = |time: f64| Ray::with_time(Point3 { x: 2.0, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 }, time);
+
+    // only halfway through is the sphere under the ray
+    assert!(sphere.hit(&down(0.0), 0.001, f64::INFINITY).is_none());
+    let hit = sphere.hit(&down(0.5), 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 4.0).abs() < 1e-12);
+    assert!(sphere.hit(&down(2.0), 0.001, f64::INFINITY).is_none());
+    assert_eq!(sphere.center(2.0), sphere.center1);
+
+    let bbox = sphere.bounding_box().unwrap();
+    assert_eq!(bbox.minimum, Point3 { x: -1.0, y: -1.0, z: -1.0 });
+    assert_eq!(bbox.maximum, Point3 { x: 5.0, y: 1.0, z: 1.0 });
+}
+
+#[test]
+fn test_sphere_light_sampling() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let sphere = Sphere { center: Point3 { x: 0.0, y: 0.0, z: -2.0 }, radius: 1.0, mat_ptr: mat };
+    // from outside the sphere, where it fills a cone, and from inside it
+    for origin in [Point3::new(), Point3 { x: 0.0, y: 0.3, z: -2.0 }].iter() {
+        // sampled directions all land on the sphere
+        for _ in 0..100 {
+            let direction = sphere.random_direction(*origin).unwrap();
+            assert!(sphere.hit(&Ray::new(*origin, direction), 0.001, f64::INFINITY).is_some());
+            assert!(sphere.pdf_value(*origin, direction, 0.5) > 0.0);
+        }
+        // and the density over every direction adds up to 1
+        let n = 200000;
+        let sum: f64 = (0..n).map(|_| sphere.pdf_value(*origin, Vec3::random_in_unit_vector(), 0.5)).sum();
+        assert!((4.0 * PI * sum / n as f64 - 1.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::material::Material;
+use crate::triangle::*;
+
+
+// STL models, ASCII or binary. STL stores every triangle with its own three corners,
+// identical corners are merged here so the mesh is indexed like any other.
+// The facet normals are ignored, the winding gives the outside instead.
+
+
+pub fn load_stl(path: &Path, mat_ptr: Arc<dyn Material>) -> Result<TriangleMesh, String> {
+    let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    parse_stl(&bytes, mat_ptr).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+pub fn parse_stl(bytes: &[u8], mat_ptr: Arc<dyn Material>) -> Result<TriangleMesh, String> {
+    // Binary files can also start with "solid", so the size decides first
+    let binary_count = if bytes.len() >= 84 {
+        Some(u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize)
+    } else {
+        None
+    };
+    let triangles = match binary_count {
+        Some(count) if bytes.len() == 84 + 50 * count => parse_binary(bytes, count),
+        _ if bytes.starts_with(b"solid") => parse_ascii(bytes)?,
+        Some(count) => return Err(format!("binary STL says {} triangles but is {} bytes long", count, bytes.len())),
+        None => return Err("too short to be an STL file".to_string()),
+    };
+
+    let mut positions = Vec::new();
+    let mut index_of: HashMap<[u64; 3], usize> = HashMap::new();
+    let mut faces = Vec::with_capacity(triangles.len());
+    for triangle in triangles {
+        let vertices = triangle.map(|p| {
+            *index_of.entry([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]).or_insert_with(|| {
+                positions.push(p);
+                positions.len() - 1
+            })
+        });
+        faces.push(MeshFace { vertices, normals: None, uvs: None });
+    }
+    TriangleMesh::new(positions, Vec::new(), Vec::new(), faces, mat_ptr)
+}
+
+// 80 byte header, triangle count, then per triangle a normal, three corners and two spare bytes
+fn parse_binary(bytes: &[u8], count: usize) -> Vec<[Point3; 3]> {
+    let float = |at: usize| f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as f64;
+    let point = |at: usize| Point3 { x: float(at), y: float(at + 4), z: float(at + 8) };
+
+    (0..count)
+        .map(|i| {
+            // skip the normal
+            let corners = 84 + 50 * i + 12;
+            [point(corners), point(corners + 12), point(corners + 24)]
+        })
+        .collect()
+}
+
+fn parse_ascii(bytes: &[u8]) -> Result<Vec<[Point3; 3]>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "ascii STL is not text")?;
+
+    let mut triangles = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["vertex", x, y, z] => {
+                let parse = |s: &str| s.parse::<f64>().map_err(|_| format!("line {}: bad number `{}`", i + 1, s));
+                corners.push(Point3 { x: parse(x)?, y: parse(y)?, z: parse(z)? });
+            }
+            ["endloop"] => {
+                if corners.len() != 3 {
+                    return Err(format!("line {}: facet has {} vertices, expected 3", i + 1, corners.len()));
+                }
+                triangles.push([corners[0], corners[1], corners[2]]);
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+
+
+// TEST
+#[test]
+fn test_parse_stl() {
+    let ascii = "solid square
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 1 1 0
+  endloop
+endfacet
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 1 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid square
+";
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let mesh = parse_stl(ascii.as_bytes(), mat.clone()).unwrap();
+    // the shared corners are merged
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.faces[1].vertices, [0, 2, 3]);
+
+    // the same square in binary, with a header that starts with "solid" to be awkward
+    let mut binary = b"solid but actually binary".to_vec();
+    binary.resize(80, 0);
+    binary.extend_from_slice(&2u32.to_le_bytes());
+    for triangle in [[[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]], [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]]].iter() {
+        for v in [[0.0f32, 0.0, 1.0]].iter().chain(triangle.iter()).flatten() {
+            binary.extend_from_slice(&v.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0, 0]);
+    }
+    let mesh = parse_stl(&binary, mat.clone()).unwrap();
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.faces.len(), 2);
+
+    binary.pop();
+    assert!(parse_stl(&binary, mat).is_err());
+}
@@ -0,0 +1,194 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::image_io::*;
+use crate::perlin::*;
+
+
+// Anything that can give a colour for a point on a surface.
+// (u,v) are the surface coordinates from the HitRecord, p is the hit point in world space.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Colour;
+}
+
+
+pub struct SolidColour {
+    pub colour: Colour,
+}
+
+impl SolidColour {
+    pub fn new(colour: Colour) -> SolidColour {
+        SolidColour { colour }
+    }
+}
+
+impl Texture for SolidColour {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Colour {
+        self.colour
+    }
+}
+
+
+// 3D checker board, alternates between the two textures every `scale` units along each axis.
+// Being solid it does not care about (u,v), so it works on any shape.
+pub struct CheckerTexture {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub scale: f64,
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Colour {
+        let x = (p.x / self.scale).floor() as i64;
+        let y = (p.y / self.scale).floor() as i64;
+        let z = (p.z / self.scale).floor() as i64;
+
+        if (x + y + z).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+
+// An image wrapped around the surface using its (u,v) coordinates,
+// u = 0 is the left edge of the image and v = 0 the bottom.
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+
+    pub fn new(image: Image) -> ImageTexture {
+        ImageTexture { image }
+    }
+
+    pub fn load(path: &Path) -> Result<ImageTexture, String> {
+        Ok(ImageTexture::new(read_image(path)?))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Colour {
+        if self.image.pixels.is_empty() {
+            // cyan, so a missing image is obvious in the render
+            return Colour { x: 0.0, y: 1.0, z: 1.0 };
+        }
+
+        let u = u.clamp(0.0, 1.0);
+        // flip v, image rows go top to bottom
+        let v = 1.0 - v.clamp(0.0, 1.0);
+
+        let i = ((u * self.image.width as f64) as u32).min(self.image.width - 1);
+        let j = ((v * self.image.height as f64) as u32).min(self.image.height - 1);
+        self.image.pixels[(j * self.image.width + i) as usize]
+    }
+}
+
+
+fn lerp(a: Colour, b: Colour, t: f64) -> Colour {
+    (1.0 - t) * a + t * b
+}
+
+
+// Grey fractal noise, a single octave is plain Perlin noise
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    // frequency, higher is finer
+    pub scale: f64,
+    pub octaves: u32,
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Colour {
+        let n = self.noise.fbm(self.scale * p, self.octaves, 2.0, 0.5);
+        Colour { x: 1.0, y: 1.0, z: 1.0 } * (0.5 * (1.0 + n)).clamp(0.0, 1.0)
+    }
+}
+
+
+// Sine bands along z, pushed around by turbulence to make the veins
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    // how far the veins wander
+    pub turbulence: f64,
+    pub octaves: u32,
+    pub light: Colour,
+    pub dark: Colour,
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Colour {
+        let phase = self.scale * p.z + self.turbulence * self.noise.turbulence(p, self.octaves);
+        lerp(self.dark, self.light, 0.5 * (1.0 + phase.sin()))
+    }
+}
+
+
+// Growth rings around the y axis, wobbled with fractal noise
+pub struct WoodTexture {
+    pub noise: Perlin,
+    // rings per unit
+    pub scale: f64,
+    pub turbulence: f64,
+    pub octaves: u32,
+    pub light: Colour,
+    pub dark: Colour,
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Colour {
+        let radius = (p.x * p.x + p.z * p.z).sqrt();
+        let rings = radius * self.scale + self.turbulence * self.noise.fbm(p, self.octaves, 2.0, 0.5);
+        // sharpen each ring so the dark late wood is thinner than the light early wood
+        let t = (rings - rings.floor()).powi(3);
+        lerp(self.light, self.dark, t)
+    }
+}
+
+
+// TEST
+#[test]
+fn test_checker_alternates() {
+    let checker = CheckerTexture {
+        even: Arc::new(SolidColour::new(Colour { x: 1.0, y: 1.0, z: 1.0 })),
+        odd: Arc::new(SolidColour::new(Colour::new())),
+        scale: 1.0,
+    };
+    let white = checker.value(0.0, 0.0, Point3 { x: 0.5, y: 0.5, z: 0.5 });
+    let black = checker.value(0.0, 0.0, Point3 { x: 1.5, y: 0.5, z: 0.5 });
+    let negative = checker.value(0.0, 0.0, Point3 { x: -0.5, y: 0.5, z: 0.5 });
+    assert_eq!(white, Colour { x: 1.0, y: 1.0, z: 1.0 });
+    assert_eq!(black, Colour::new());
+    assert_eq!(negative, Colour::new());
+}
+
+#[test]
+fn test_noise_textures_stay_in_range() {
+    let white = Colour { x: 1.0, y: 1.0, z: 1.0 };
+    let noise = NoiseTexture { noise: Perlin::new(3), scale: 4.0, octaves: 6 };
+    let marble = MarbleTexture { noise: Perlin::new(3), scale: 4.0, turbulence: 10.0, octaves: 7, light: white, dark: Colour::new() };
+    let wood = WoodTexture { noise: Perlin::new(3), scale: 8.0, turbulence: 0.5, octaves: 4, light: white, dark: Colour::new() };
+    let in_range = |c: Colour| [c.x, c.y, c.z].iter().all(|v| (0.0..=1.0).contains(v));
+
+    for i in 0..200 {
+        let p = Point3 { x: i as f64 * 0.31, y: i as f64 * 0.17, z: i as f64 * -0.23 };
+        assert!(in_range(noise.value(0.0, 0.0, p)));
+        assert!(in_range(marble.value(0.0, 0.0, p)));
+        assert!(in_range(wood.value(0.0, 0.0, p)));
+    }
+}
+
+#[test]
+fn test_image_texture_lookup() {
+    let red = Colour { x: 1.0, y: 0.0, z: 0.0 };
+    let blue = Colour { x: 0.0, y: 0.0, z: 1.0 };
+    // top row red, bottom row blue
+    let texture = ImageTexture::new(Image { width: 1, height: 2, pixels: vec![red, blue] });
+    assert_eq!(texture.value(0.5, 0.9, Point3::new()), red);
+    assert_eq!(texture.value(0.5, 0.1, Point3::new()), blue);
+    assert_eq!(texture.value(2.0, -1.0, Point3::new()), blue);
+}
@@ -0,0 +1,414 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::aabb::*;
+use std::ops::Mul;
+use std::sync::Arc;
+
+
+// Row major 4x4 matrix for affine transforms, points are column vectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { m }
+    }
+
+    pub fn translate(offset: Vec3) -> Mat4 {
+        let mut t = Mat4::identity();
+        t.m[0][3] = offset.x;
+        t.m[1][3] = offset.y;
+        t.m[2][3] = offset.z;
+        t
+    }
+
+    pub fn scale(factors: Vec3) -> Mat4 {
+        let mut s = Mat4::identity();
+        s.m[0][0] = factors.x;
+        s.m[1][1] = factors.y;
+        s.m[2][2] = factors.z;
+        s
+    }
+
+    // Right handed rotation about any axis, angle in degrees
+    pub fn rotate(axis: Vec3, degrees: f64) -> Mat4 {
+        let a = axis.unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Mat4 {
+            m: [
+                [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+                [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+                [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // The shortest rotation taking +y onto axis, for shapes built around the y axis
+    pub fn align_y(axis: Vec3) -> Mat4 {
+        let up = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+        let axis = axis.unit_vector();
+        let turn = up.cross(axis);
+        if turn.len_sqred() > 1e-12 {
+            Mat4::rotate(turn, up.dot(axis).clamp(-1.0, 1.0).acos().to_degrees())
+        } else if axis.y > 0.0 {
+            Mat4::identity()
+        } else {
+            Mat4::rotate(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, 180.0)
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = self.m[j][i];
+            }
+        }
+        t
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+    // or holds anything that is not a finite number
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if !a[pivot][col].is_finite() || a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        if inv.iter().flatten().any(|x| !x.is_finite()) {
+            return None;
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        Point3 {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        }
+    }
+
+    // Directions ignore the translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    // (a * b) applies b first, then a
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+
+// Places a shared object in the world with a transform.
+// Rays are moved into the object's own space to be tested, and the hit is moved back out,
+// so the same object (a mesh, say) can be instanced many times without copying it.
+pub struct Instance {
+    pub object: Arc<dyn Hittable>,
+    // object space to world space
+    pub to_world: Mat4,
+    pub to_object: Mat4,
+    bbox: Option<Aabb>,
+}
+
+impl Instance {
+
+    pub fn new(object: Arc<dyn Hittable>, to_world: Mat4) -> Result<Instance, String> {
+        let to_object = to_world.inverse().ok_or("transform cannot be undone, is something scaled by 0?")?;
+
+        // the box around all eight corners of the object's box, once they are transformed
+        let bbox = object.bounding_box().map(|b| transformed_box(&to_world, b));
+
+        Ok(Instance { object, to_world, to_object, bbox })
+    }
+}
+
+// The box around all eight corners of b, once they are transformed
+fn transformed_box(to_world: &Mat4, b: Aabb) -> Aabb {
+    let mut corners = (0..8).map(|i| to_world.transform_point(corner(b, i)));
+    let first = corners.next().unwrap();
+    corners.fold(Aabb::new(first, first), |acc, p| Aabb::surrounding_box(acc, Aabb::new(p, p)))
+}
+
+fn corner(b: Aabb, i: usize) -> Point3 {
+    Point3 {
+        x: if i & 1 == 0 { b.minimum.x } else { b.maximum.x },
+        y: if i & 2 == 0 { b.minimum.y } else { b.maximum.y },
+        z: if i & 4 == 0 { b.minimum.z } else { b.maximum.z },
+    }
+}
+
+
+// A transform kept as its parts, so two of them can be blended for things that move:
+// scale, then rotate about x, y and z (in degrees), then translate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub scale: Vec3,
+    pub rotate: Vec3,
+    pub translate: Vec3,
+}
+
+impl Placement {
+
+    pub fn identity() -> Placement {
+        Placement {
+            scale: Vec3 { x: 1.0, y: 1.0, z: 1.0 },
+            rotate: Vec3::new(),
+            translate: Vec3::new(),
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translate(self.translate)
+            * Mat4::rotate(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, self.rotate.z)
+            * Mat4::rotate(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, self.rotate.y)
+            * Mat4::rotate(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, self.rotate.x)
+            * Mat4::scale(self.scale)
+    }
+
+    // Each part undone in reverse, cheaper and steadier than inverting the matrix
+    pub fn inverse_matrix(&self) -> Mat4 {
+        let s = self.scale;
+        Mat4::scale(Vec3 { x: 1.0 / s.x, y: 1.0 / s.y, z: 1.0 / s.z })
+            * Mat4::rotate(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, -self.rotate.x)
+            * Mat4::rotate(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, -self.rotate.y)
+            * Mat4::rotate(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, -self.rotate.z)
+            * Mat4::translate(-self.translate)
+    }
+
+    // f = 0 gives self, f = 1 gives other
+    pub fn lerp(&self, other: &Placement, f: f64) -> Placement {
+        Placement {
+            scale: self.scale + f * (other.scale - self.scale),
+            rotate: self.rotate + f * (other.rotate - self.rotate),
+            translate: self.translate + f * (other.translate - self.translate),
+        }
+    }
+}
+
+
+// An instance that moves from one placement at time0 to another at time1, staying put
+// before and after. The transform is worked out afresh for each ray's time.
+pub struct MovingInstance {
+    pub object: Arc<dyn Hittable>,
+    pub start: Placement,
+    pub end: Placement,
+    pub time0: f64,
+    pub time1: f64,
+    bbox: Option<Aabb>,
+}
+
+impl MovingInstance {
+
+    pub fn new(object: Arc<dyn Hittable>, start: Placement, end: Placement, time0: f64, time1: f64) -> Result<MovingInstance, String> {
+        // a scale passing through zero would flatten the object somewhere along the way
+        let (s0, s1) = (start.scale, end.scale);
+        if s0.x * s1.x <= 0.0 || s0.y * s1.y <= 0.0 || s0.z * s1.z <= 0.0 {
+            return Err("scale cannot be or pass through 0".to_string());
+        }
+
+        // The boxes at a number of steps along the way, grown by how far any corner
+        // can travel in half a step, so nothing between the steps pokes out
+        const STEPS: usize = 16;
+        let bbox = object.bounding_box().map(|b| {
+            let reach = (0..8).map(|i| corner(b, i).len()).fold(0.0, f64::max);
+            let largest = |s: Vec3| s.x.abs().max(s.y.abs()).max(s.z.abs());
+            let turn = end.rotate - start.rotate;
+            let turn = (turn.x.abs() + turn.y.abs() + turn.z.abs()).to_radians();
+            let travel = (end.translate - start.translate).len()
+                + largest(end.scale - start.scale) * reach
+                + turn * largest(s0).max(largest(s1)) * reach;
+            let pad = travel / (2 * STEPS) as f64;
+            let pad = Vec3 { x: pad, y: pad, z: pad };
+
+            (0..=STEPS)
+                .map(|i| transformed_box(&start.lerp(&end, i as f64 / STEPS as f64).matrix(), b))
+                .fold(None, |acc: Option<Aabb>, step| Some(acc.map_or(step, |acc| Aabb::surrounding_box(acc, step))))
+                .map(|b| Aabb::new(b.minimum - pad, b.maximum + pad))
+                .unwrap()
+        });
+
+        Ok(MovingInstance { object, start, end, time0, time1, bbox })
+    }
+
+    pub fn placement(&self, time: f64) -> Placement {
+        if self.time1 <= self.time0 {
+            return self.start;
+        }
+        let f = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.start.lerp(&self.end, f)
+    }
+}
+
+impl Hittable for MovingInstance {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let placement = self.placement(ray.time);
+        let to_object = placement.inverse_matrix();
+        let hit = self.object.hit(&to_local(&to_object, ray), tmin, tmax)?;
+        Some(to_world_hit(&placement.matrix(), &to_object, hit))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let placement = self.placement(ray.time);
+        let (to_world, to_object) = (placement.matrix(), placement.inverse_matrix());
+        let intervals = self.object.intervals(&to_local(&to_object, ray))?;
+        let to_world = |hit| to_world_hit(&to_world, &to_object, hit);
+        Some(intervals.into_iter().map(|(enter, exit)| (to_world(enter), to_world(exit))).collect())
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+// the direction is left unnormalised so t means the same thing in both spaces
+fn to_local(to_object: &Mat4, ray: &Ray) -> Ray {
+    Ray::with_time(to_object.transform_point(ray.orig), to_object.transform_vector(ray.dir), ray.time)
+}
+
+fn to_world_hit(to_world: &Mat4, to_object: &Mat4, mut hit: HitRecord) -> HitRecord {
+    hit.p = to_world.transform_point(hit.p);
+    // normals go through the inverse transpose to stay perpendicular under non uniform scaling.
+    // That also keeps their side relative to the ray, so front_face does not change
+    hit.normal = to_object.transpose().transform_vector(hit.normal).unit_vector();
+    hit
+}
+
+impl Hittable for Instance {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let hit = self.object.hit(&to_local(&self.to_object, ray), tmin, tmax)?;
+        Some(to_world_hit(&self.to_world, &self.to_object, hit))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let intervals = self.object.intervals(&to_local(&self.to_object, ray))?;
+        let to_world = |hit| to_world_hit(&self.to_world, &self.to_object, hit);
+        Some(intervals.into_iter().map(|(enter, exit)| (to_world(enter), to_world(exit))).collect())
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_mat4_inverse() {
+    let m = Mat4::translate(Vec3 { x: 1.0, y: -2.0, z: 3.0 })
+        * Mat4::rotate(Vec3 { x: 1.0, y: 1.0, z: 0.0 }, 30.0)
+        * Mat4::scale(Vec3 { x: 2.0, y: 0.5, z: 1.0 });
+    let product = m * m.inverse().unwrap();
+    for i in 0..4 {
+        for j in 0..4 {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            assert!((product.m[i][j] - expected).abs() < 1e-12);
+        }
+    }
+    assert!(Mat4::scale(Vec3 { x: 1.0, y: 0.0, z: 1.0 }).inverse().is_none());
+    assert!(Mat4::scale(Vec3 { x: 1.0, y: f64::NAN, z: 1.0 }).inverse().is_none());
+    assert!(Mat4::rotate(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, f64::INFINITY).inverse().is_none());
+
+    let p = Mat4::rotate(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, 90.0).transform_point(Point3 { x: 1.0, y: 0.0, z: 0.0 });
+    assert!((p - Point3 { x: 0.0, y: 0.0, z: -1.0 }).len() < 1e-12);
+}
+
+#[test]
+fn test_instance_hit() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let sphere = Arc::new(crate::sphere::Sphere { center: Point3::new(), radius: 1.0, mat_ptr: mat });
+    // squashed to half height, then moved up
+    let transform = Mat4::translate(Vec3 { x: 0.0, y: 5.0, z: 0.0 }) * Mat4::scale(Vec3 { x: 1.0, y: 0.5, z: 1.0 });
+    let instance = Instance::new(sphere, transform).unwrap();
+
+    let bbox = instance.bounding_box().unwrap();
+    assert!((bbox.minimum - Point3 { x: -1.0, y: 4.5, z: -1.0 }).len() < 1e-12);
+    assert!((bbox.maximum - Point3 { x: 1.0, y: 5.5, z: 1.0 }).len() < 1e-12);
+
+    let down = Ray::new(Point3 { x: 0.0, y: 10.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = instance.hit(&down, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 4.5).abs() < 1e-12);
+    assert!((hit.p - Point3 { x: 0.0, y: 5.5, z: 0.0 }).len() < 1e-12);
+    assert!((hit.normal - Vec3 { x: 0.0, y: 1.0, z: 0.0 }).len() < 1e-12);
+    assert!(hit.front_face);
+
+    // off to the side the squashed normal leans further up than the sphere's would
+    let side = Ray::new(Point3 { x: 0.6, y: 10.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = instance.hit(&side, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.normal.len() - 1.0).abs() < 1e-12);
+    assert!(hit.normal.y > 0.8);
+}
+
+#[test]
+fn test_moving_instance() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let cube = Arc::new(crate::planar::BoxShape::new(Point3 { x: -1.0, y: -1.0, z: -1.0 }, Point3 { x: 1.0, y: 1.0, z: 1.0 }, mat));
+    let start = Placement::identity();
+    let end = Placement { rotate: Vec3 { x: 0.0, y: 90.0, z: 0.0 }, translate: Vec3 { x: 4.0, y: 0.0, z: 0.0 }, ..start };
+    let moving = MovingInstance::new(cube, start, end, 0.0, 1.0).unwrap();
+
+    let p = moving.placement(0.5);
+    assert!((p.matrix() * p.inverse_matrix()).m.iter().flatten().zip(Mat4::identity().m.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-12));
+
+    // halfway along the cube has turned 45 degrees, so its edge reaches sqrt(2) out
+    let down = Ray::with_time(Point3 { x: 2.0 + 1.3, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 }, 0.5);
+    assert!(moving.hit(&down, 0.001, f64::INFINITY).is_some());
+    let early = Ray { time: 0.0, ..down };
+    assert!(moving.hit(&early, 0.001, f64::INFINITY).is_none());
+
+    // the box holds the cube all the way through the turn
+    let bbox = moving.bounding_box().unwrap();
+    assert!(bbox.minimum.x <= -1.0 && bbox.maximum.x >= 5.0);
+    assert!(bbox.maximum.z >= 2f64.sqrt());
+}
@@ -0,0 +1,272 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::*;
+use crate::bvh::*;
+use std::sync::Arc;
+
+
+// One face of a mesh, indices into the mesh's shared arrays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshFace {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+// Triangles sharing their vertex, normal and texture coordinate arrays.
+// Faces with normals are smooth shaded, the rest use the flat face normal.
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    // one per position, or empty
+    pub colours: Vec<Colour>,
+    pub faces: Vec<MeshFace>,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl TriangleMesh {
+
+    // Checks every face index is in range, so hit never has to
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<MeshFace>,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Result<TriangleMesh, String> {
+        if faces.is_empty() {
+            return Err("mesh has no faces".to_string());
+        }
+        for (i, face) in faces.iter().enumerate() {
+            let check = |indices: [usize; 3], len: usize, what: &str| match indices.iter().find(|&&index| index >= len) {
+                Some(index) => Err(format!("face {} uses {} {} but there are only {}", i, what, index, len)),
+                None => Ok(()),
+            };
+            check(face.vertices, positions.len(), "vertex")?;
+            if let Some(n) = face.normals {
+                check(n, normals.len(), "normal")?;
+            }
+            if let Some(uv) = face.uvs {
+                check(uv, uvs.len(), "uv")?;
+            }
+        }
+        Ok(TriangleMesh { positions, normals, uvs, colours: Vec::new(), faces, mat_ptr })
+    }
+
+    // Per vertex colours, interpolated across each face to tint the material
+    pub fn with_colours(mut self, colours: Vec<Colour>) -> Result<TriangleMesh, String> {
+        if colours.len() != self.positions.len() {
+            return Err(format!("mesh has {} vertices but {} colours", self.positions.len(), colours.len()));
+        }
+        self.colours = colours;
+        Ok(self)
+    }
+
+    // A mesh of one flat triangle
+    pub fn triangle(a: Point3, b: Point3, c: Point3, mat_ptr: Arc<dyn Material>) -> TriangleMesh {
+        let face = MeshFace { vertices: [0, 1, 2], normals: None, uvs: None };
+        TriangleMesh { positions: vec![a, b, c], normals: Vec::new(), uvs: Vec::new(), colours: Vec::new(), faces: vec![face], mat_ptr }
+    }
+
+    pub fn triangles(self) -> Vec<Arc<dyn Hittable>> {
+        let mesh = Arc::new(self);
+        (0..mesh.faces.len())
+            .map(|face| Arc::new(Triangle { mesh: mesh.clone(), face }) as Arc<dyn Hittable>)
+            .collect()
+    }
+
+    // The faces in their own BVH, so the mesh can be added to a world as one object
+    pub fn into_bvh(self) -> BvhNode {
+        BvhNode::new(self.triangles())
+    }
+}
+
+
+// A single face of a TriangleMesh
+pub struct Triangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub face: usize,
+}
+
+impl Triangle {
+
+    fn corners(&self) -> [Point3; 3] {
+        let [a, b, c] = self.mesh.faces[self.face].vertices;
+        [self.mesh.positions[a], self.mesh.positions[b], self.mesh.positions[c]]
+    }
+}
+
+// Watertight ray/triangle test from Woop, Benthin and Wald (2013).
+// The triangle is moved into a space where the ray starts at the origin and runs along +z,
+// then the edge functions decide the hit. Rays through a shared edge or vertex always hit
+// exactly one of the triangles around it, so meshes have no cracks.
+// Returns t and the barycentric weights of the three corners.
+pub fn intersect_triangle(ray: &Ray, corners: [Point3; 3]) -> Option<(f64, [f64; 3])> {
+    let d = ray.dir;
+    let kz = if d.x.abs() > d.y.abs() {
+        if d.x.abs() > d.z.abs() { 0 } else { 2 }
+    } else if d.y.abs() > d.z.abs() { 1 } else { 2 };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    // keep the winding the same after the axes are permuted
+    if d[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let sx = d[kx] / d[kz];
+    let sy = d[ky] / d[kz];
+    let sz = 1.0 / d[kz];
+
+    let a = corners[0] - ray.orig;
+    let b = corners[1] - ray.orig;
+    let c = corners[2] - ray.orig;
+
+    // shear so the ray points down z
+    let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+    let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+    let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    Some((t, [u / det, v / det, w / det]))
+}
+
+impl Hittable for Triangle {
+
+    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+        let corners = self.corners();
+        let (t, [b0, b1, b2]) = intersect_triangle(ray, corners)?;
+        if t <= tmin || t >= tmax {
+            return None;
+        }
+
+        let mesh = &self.mesh;
+        let face = &mesh.faces[self.face];
+        let mut geometric_normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]).unit_vector();
+
+        // the interpolated normal decides which side is outside, the winding only matters without one
+        let outward_normal = match face.normals {
+            Some([n0, n1, n2]) => {
+                let n = b0 * mesh.normals[n0] + b1 * mesh.normals[n1] + b2 * mesh.normals[n2];
+                if n.len_sqred() > 0.0 {
+                    let n = n.unit_vector();
+                    if n.dot(geometric_normal) < 0.0 {
+                        geometric_normal = -geometric_normal;
+                    }
+                    n
+                } else {
+                    geometric_normal
+                }
+            }
+            None => geometric_normal,
+        };
+
+        let (u, v) = match face.uvs {
+            Some([t0, t1, t2]) => {
+                let (uv0, uv1, uv2) = (mesh.uvs[t0], mesh.uvs[t1], mesh.uvs[t2]);
+                (b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0, b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1)
+            }
+            None => (b1, b2),
+        };
+
+        let colour = if mesh.colours.is_empty() {
+            None
+        } else {
+            let [c0, c1, c2] = face.vertices;
+            Some(b0 * mesh.colours[c0] + b1 * mesh.colours[c1] + b2 * mesh.colours[c2])
+        };
+
+        // front_face comes from the real surface, so a ray grazing a smooth shaded
+        // silhouette is not mistaken for one leaving the object
+        let front_face = ray.dir.dot(geometric_normal) < 0.0;
+        let normal = if front_face { outward_normal } else { -outward_normal };
+
+        Some(HitRecord {
+            p: b0 * corners[0] + b1 * corners[1] + b2 * corners[2],
+            normal,
+            mat_ptr: mesh.mat_ptr.clone(),
+            t,
+            u,
+            v,
+            front_face,
+            colour,
+            sampled_light: false,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [a, b, c] = self.corners();
+        Some(Aabb::surrounding_box(Aabb::new(a, b), Aabb::new(a, c)).padded())
+    }
+}
+
+
+
+// TEST
+#[test]
+fn test_triangle_hit() {
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let mesh = TriangleMesh::triangle(
+        Point3 { x: -1.0, y: -1.0, z: 0.0 },
+        Point3 { x: 1.0, y: -1.0, z: 0.0 },
+        Point3 { x: 0.0, y: 1.0, z: 0.0 },
+        mat,
+    );
+    let triangle = &mesh.triangles()[0];
+
+    let front = Ray::new(Point3 { x: 0.0, y: 0.0, z: 2.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let hit = triangle.hit(&front, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.t - 2.0).abs() < 1e-12);
+    assert!(hit.front_face);
+    assert_eq!(hit.normal, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+
+    let back = Ray::new(Point3 { x: 0.0, y: 0.0, z: -2.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = triangle.hit(&back, 0.001, f64::INFINITY).unwrap();
+    assert!(!hit.front_face);
+    assert_eq!(hit.normal, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+
+    let miss = Ray::new(Point3 { x: 2.0, y: 0.0, z: 2.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    assert!(triangle.hit(&miss, 0.001, f64::INFINITY).is_none());
+    assert!(triangle.hit(&front, 0.001, 1.0).is_none());
+}
+
+#[test]
+fn test_mesh_shared_edge_is_watertight() {
+    // a square split along its diagonal, rays straight down the diagonal must hit one of the halves
+    let mat = Arc::new(crate::material::Lambertian::new(Colour::new()));
+    let positions = vec![
+        Point3 { x: 0.0, y: 0.0, z: 0.0 },
+        Point3 { x: 1.0, y: 0.0, z: 0.0 },
+        Point3 { x: 1.0, y: 1.0, z: 0.0 },
+        Point3 { x: 0.0, y: 1.0, z: 0.0 },
+    ];
+    let faces = vec![
+        MeshFace { vertices: [0, 1, 2], normals: None, uvs: None },
+        MeshFace { vertices: [0, 2, 3], normals: None, uvs: None },
+    ];
+    let mesh = TriangleMesh::new(positions, Vec::new(), Vec::new(), faces, mat.clone()).unwrap().into_bvh();
+
+    for i in 1..100 {
+        let s = i as f64 / 100.0;
+        let ray = Ray::new(Point3 { x: s, y: s, z: 1.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+        assert!(mesh.hit(&ray, 0.001, f64::INFINITY).is_some(), "gap at {}", s);
+    }
+
+    let bad = vec![MeshFace { vertices: [0, 1, 5], normals: None, uvs: None }];
+    assert!(TriangleMesh::new(vec![Point3::new(); 3], Vec::new(), Vec::new(), bad, mat).is_err());
+}
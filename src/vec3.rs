@@ -1,12 +1,12 @@
 use std::fmt;
-use std::ops::{Add, Div, Mul, Neg, Sub};
-use rand::prelude::*;
+use std::ops::{Add, Div, Index, Mul, Neg, Sub};
+use crate::random::*;
 use std::f64::consts::PI;
 
 pub type Point3 = Vec3;
 pub type Colour = Vec3;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
 pub struct Vec3 {
     pub x: f64,
     pub y: f64,
@@ -22,8 +22,8 @@ impl Vec3 {
         }
     }
 
-    pub fn dot(mut self, other: Vec3) -> f64 {
-        return self.x * other.x + self.y * other.y + self.z * other.z;
+    pub fn dot(self, other: Vec3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
     }
 
     pub fn cross(self, other: Vec3) -> Vec3 {
@@ -43,26 +43,24 @@ impl Vec3 {
     }
 
     pub fn len_sqred(self) -> f64 {
-        return &self.x * &self.x + &self.y * &self.y + &self.z * &self.z;
+        self.x * self.x + self.y * self.y + self.z * self.z
     }
 
     // random vector
     pub fn random() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        return  Vec3 {
-            x: rng.gen::<f64>(),
-            y: rng.gen::<f64>(),
-            z: rng.gen::<f64>(),
+        Vec3 {
+            x: random_double(),
+            y: random_double(),
+            z: random_double(),
         }
     }
 
 
     pub fn random_range(min:f64,max:f64) -> Vec3 {
-        let mut rng = rand::thread_rng();
-        return  Vec3 {
-            x: rng.gen_range(min, max),
-            y: rng.gen_range(min, max),
-            z: rng.gen_range(min, max),
+        Vec3 {
+            x: random_range(min, max),
+            y: random_range(min, max),
+            z: random_range(min, max),
         }
     }
 
@@ -77,31 +75,39 @@ impl Vec3 {
     }
 
    pub fn random_in_unit_vector() -> Vec3 {
-        let mut rng = rand::thread_rng();
-
-        let a = rng.gen_range(0.0, 2.0*PI);
-        let z = rng.gen_range(-1.0, 1.0);
+        let a = random_range(0.0, 2.0*PI);
+        let z = random_range(-1.0, 1.0);
         let r = f64::sqrt(1.0-z*z);
 
-        return  Vec3 {
+        Vec3 {
             x: r* a.cos(),
             y: r* a.sin(),
-            z: z,
+            z,
+        }
+    }
+
+    // About +z, with more directions near the pole in proportion to the cosine, so pdf = z / pi
+    pub fn random_cosine_direction() -> Vec3 {
+        let phi = random_range(0.0, 2.0*PI);
+        let r2 = random_double();
+        let r = r2.sqrt();
+
+        Vec3 {
+            x: r * phi.cos(),
+            y: r * phi.sin(),
+            z: (1.0 - r2).sqrt(),
         }
     }
 
     pub fn random_in_unit_disk() -> Vec3{
-        let mut rng = rand::thread_rng();
-        let mut vec= Vec3::new();
-        while(true){
-            vec = Vec3 { x:rng.gen_range(-1.0, 1.0)
-                                , y:rng.gen_range(-1.0, 1.0)
+        loop {
+            let vec = Vec3 { x:random_range(-1.0, 1.0)
+                                , y:random_range(-1.0, 1.0)
                                 , z:0.0,  
                                 };
-            if (vec.len_sqred() >=1.0){ continue }
-            break;
+            if vec.len_sqred() >=1.0 { continue }
+            return vec;
         }
-        vec 
     }
 
     // vec3 random_in_unit_disk() {
@@ -115,9 +121,9 @@ impl Vec3 {
    pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
         let in_unit_sphere = Vec3::random_in_unit_sphere();
         if  in_unit_sphere.dot(normal)  > 0.0 {
-            return in_unit_sphere;
+            in_unit_sphere
         } else {
-            return -in_unit_sphere;
+            -in_unit_sphere
         }
     }
 
@@ -126,11 +132,11 @@ impl Vec3 {
         let cos_theta = (-uv).dot(n);
         let r_out_perp:Vec3 = etai_over_etat * (uv+cos_theta*n);
         let r_out_parallel = -f64::sqrt((1.0-r_out_perp.len_sqred()).abs()) *n;
-        return r_out_perp + r_out_parallel;
+        r_out_perp + r_out_parallel
     }
 
     pub fn reflect( v : Vec3, n : Vec3  ) -> Vec3 {
-        return  v - 2.0*v.dot(n)*n;
+        v - 2.0*v.dot(n)*n
     }
 
 
@@ -140,43 +146,14 @@ impl Vec3 {
 
 
 
-impl Colour{
-  pub fn write_colour(self, samples_per_pixel:i32) {
-        let mut r = self.x;
-        let mut g = self.y;
-        let mut b = self.z;
-
-        // divide the colour by number of samples
-        let scale = 1.0 / samples_per_pixel as f64;
-        // the sqrt is to Gamma correct for gamma 2.0
-        r = f64::sqrt(r*scale);
-        g = f64::sqrt(g*scale);        
-        b = f64::sqrt(b*scale);        
-
-
-        let i_r = (255.999 * clamp(r,0.0,0.999)).round() as u16;
-        let i_g = (255.999 * clamp(g,0.0,0.999)).round() as u16;
-        let i_b = (255.999 * clamp(b,0.0,0.999)).round() as u16;
-        print!("{} {} {}\n", i_r, i_g, i_b)
-    }
-}
-
-
-fn clamp(x:f64, min:f64, max:f64) -> f64 {
-    if x < min { return min; }
-    if x > max { return max; }
-    return x;
-}
-
-
 // 
 impl Neg for Vec3 {
     type Output = Vec3;
     fn neg(self) -> Vec3 {
         Vec3 {
-            x: -&self.x,
-            y: -&self.y,
-            z: -&self.z,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
         }
     }
 }
@@ -186,9 +163,9 @@ impl Sub for Vec3 {
 
     fn sub(self, other: Vec3) -> Vec3 {
         Vec3 {
-            x: &self.x - other.x,
-            y: &self.y - other.y,
-            z: &self.z - other.z,
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
         }
     }
 }
@@ -198,9 +175,9 @@ impl Add for Vec3 {
 
     fn add(self, other: Vec3) -> Vec3 {
         Vec3 {
-            x: &self.x + other.x,
-            y: &self.y + other.y,
-            z: &self.z + other.z,
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
         }
     }
 }
@@ -223,9 +200,9 @@ impl Mul<f64> for Vec3 {
 
     fn mul(self, other: f64) -> Vec3 {
         Vec3 {
-            x: &self.x * other,
-            y: &self.y * other,
-            z: &self.z * other,
+            x: self.x * other,
+            y: self.y * other,
+            z: self.z * other,
         }
     }
 }
@@ -235,9 +212,9 @@ impl Mul<Vec3> for Vec3 {
 
     fn mul(self, other: Vec3) -> Vec3 {
         Vec3 {
-            x: &self.x * other.x,
-            y: &self.y * other.y,
-            z: &self.z * other.z,
+            x: self.x * other.x,
+            y: self.y * other.y,
+            z: self.z * other.z,
         }
     }
 }
@@ -247,9 +224,22 @@ impl Div<f64> for Vec3 {
 
     fn div(self, other: f64) -> Vec3 {
         Vec3 {
-            x: &self.x / other,
-            y: &self.y / other,
-            z: &self.z / other,
+            x: self.x / other,
+            y: self.y / other,
+            z: self.z / other,
+        }
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis out of range: {}", axis),
         }
     }
 }
@@ -260,11 +250,35 @@ impl fmt::Display for Vec3 {
     }
 }
 
-pub fn unit_vector(mut v:Vec3) -> Vec3 {
+pub fn unit_vector(v:Vec3) -> Vec3 {
     v/ v.len()
 }
 
 
+// Orthonormal basis around w, for turning directions sampled about +z into world space
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+
+    pub fn build_from_w(w: Vec3) -> Onb {
+        let w = w.unit_vector();
+        let a = if w.x.abs() > 0.9 { Vec3 { x: 0.0, y: 1.0, z: 0.0 } } else { Vec3 { x: 1.0, y: 0.0, z: 0.0 } };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+        Onb { u, v, w }
+    }
+
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
+
+
 
 
 